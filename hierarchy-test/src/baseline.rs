@@ -1,105 +1,155 @@
-extern crate histogram;
 extern crate postgres;
 extern crate uuid;
 
 use std::sync::Arc;
 use std::thread;
-use std::time::Instant;
 
-use histogram::Histogram;
-use postgres::{Connection, TlsMode};
+use pg_test_common::histogram::Histogram;
+use pg_test_common::metrics;
+use pg_test_common::replica::Lag;
+use pg_test_common::settings::Settings;
+use pg_test_common::explain::Explainer;
+use pg_test_common::work::{self, Work};
+use postgres::Connection;
 use rand::{thread_rng};
 
-use common;
+use catalog::WorkerMemory;
+use common::{self, Commits};
 use types::{HistogramPair, MantaObject};
 
+pub static WRITE_SQL: &str = "INSERT INTO manta_bucket_object (id, owner, bucket_id, \
+                          name , vnode, content_length, content_md5, \
+                          content_type, headers, sharks) \
+                          VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)";
+pub static READ_SQL: &str = "SELECT * FROM manta_bucket_object WHERE owner = $1 \
+                         AND bucket_id = $2 AND name = $3";
 
-pub fn run_threads(url: Arc<String>, thread_count: &u32, thread_iterations: Arc<u32>) {
+
+pub fn run_threads(url: Arc<String>,
+                   thread_count: &u32,
+                   thread_iterations: Arc<u32>,
+                   replica_url: Option<Arc<String>>,
+                   settings: Arc<Settings>) -> (Work, HistogramPair, Lag, WorkerMemory) {
+    let (commits, checker) = common::spawn_checker(&replica_url, &settings);
     let mut handles = Vec::new();
-    for _number in 1..*thread_count {
+    for _number in 0..*thread_count {
         let url_clone = Arc::clone(&url);
         let thread_iterations_clone = Arc::clone(&thread_iterations);
+        let replica_url_clone = replica_url.clone();
+        let commits_clone = commits.clone();
+        let settings_clone = Arc::clone(&settings);
         let h = thread::spawn(|| single_schema_queries(url_clone,
-                                                       thread_iterations_clone));
+                                                       thread_iterations_clone,
+                                                       replica_url_clone,
+                                                       commits_clone,
+                                                       settings_clone));
         handles.push(h);
     }
 
+    let mut work = Work::default();
+    let mut memory = WorkerMemory::default();
     let mut read_histogram = Histogram::new();
     let mut write_histogram = Histogram::new();
 
+    // The checker finishes once every thread has dropped its sender
+    drop(commits);
+
     for handle in handles {
         match handle.join() {
-            Ok((thread_read_hist, thread_write_hist)) => {
+            Ok((thread_work, (thread_read_hist, thread_write_hist), thread_memory)) => {
+                work.add(&thread_work);
+                memory.merge(&thread_memory);
                 read_histogram.merge(&thread_read_hist);
                 write_histogram.merge(&thread_write_hist);
             }
-            Err(_) => println!("baseline thread panicked"),
+            Err(_) => report!("baseline thread panicked"),
         }
     }
 
+    let lag = common::join_checker(checker);
     common::print_results(&read_histogram, &write_histogram);
+
+    (work, (read_histogram, write_histogram), lag, memory)
 }
 
 
 
-fn single_schema_queries(url: Arc<String>, thread_iterations: Arc<u32>) -> HistogramPair {
+fn single_schema_queries(url: Arc<String>,
+                         thread_iterations: Arc<u32>,
+                         replica_url: Option<Arc<String>>,
+                         commits: Commits,
+                         settings: Arc<Settings>) -> (Work, HistogramPair, WorkerMemory) {
+    let mut work = Work::thread();
     let mut read_histogram = Histogram::new();
     let mut write_histogram = Histogram::new();
-    let conn = Connection::connect(&*url.as_str(), TlsMode::None).unwrap();
+    let conn = common::connect(&url);
+    settings.apply(&conn);
+    let replica = replica_url.map(|replica_url| {
+        let replica = common::connect_replica(&replica_url);
+        settings.apply(&replica);
+        replica
+    });
 
     let mut rng = thread_rng();
 
-    for _number in 1..*thread_iterations {
+    for _number in 0..*thread_iterations {
         let o = MantaObject::new(&mut rng);
 
-        let write_start = Instant::now();
-        let write_trans = conn.transaction().unwrap();
-        let write_sql = "INSERT INTO manta_bucket_object (id, owner, bucket_id, \
-                         name , vnode, content_length, content_md5, \
-                         content_type, headers, sharks) \
-                         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)";
-
-        write_trans.execute(write_sql,
-                            &[
-                                &o.id,
-                                &o.owner,
-                                &o.bucket_id,
-                                &o.name,
-                                &o.vnode,
-                                &o.content_length,
-                                &o.content_md5,
-                                &o.content_type,
-                                &o.headers,
-                                &o.sharks
-                            ]).unwrap();
-
-        write_trans.commit().unwrap();
-        let write_end = Instant::now();
-
-        let write_duration = write_end.duration_since(write_start);
-        let write_nanos = write_duration.as_secs() * 1_000_000_000
-            + write_duration.subsec_nanos() as u64;
-        write_histogram.increment(write_nanos).unwrap();
-
-        let read_start = Instant::now();
-        let read_trans = conn.transaction().unwrap();
-        let read_sql = "SELECT * FROM manta_bucket_object WHERE owner = $1 \
-                        AND bucket_id = $2 AND name = $3";
-
-        read_trans
-            .execute(read_sql, &[&o.owner, &o.bucket_id, &o.name])
-            .unwrap();
-
-        read_trans.commit().unwrap();
-        let read_end = Instant::now();
-
-        let read_duration = read_end.duration_since(read_start);
-        let read_nanos = read_duration.as_secs() * 1_000_000_000
-            + read_duration.subsec_nanos() as u64;
-        read_histogram.increment(read_nanos).unwrap();
+        let mut xid = None;
+        let written = metrics::timed("Write", &mut write_histogram, || {
+            let write_trans = conn.transaction()?;
+            write_trans.execute(WRITE_SQL,
+                                &[
+                                    &o.id,
+                                    &o.owner,
+                                    &o.bucket_id,
+                                    &o.name,
+                                    &o.vnode,
+                                    &o.content_length,
+                                    &o.content_md5,
+                                    &o.content_type,
+                                    &o.headers,
+                                    &o.sharks
+                                ])?;
+            if commits.is_some() {
+                xid = Some(common::txid(&write_trans)?);
+            }
+            write_trans.commit()
+        });
+        work.count(1, &written);
+        if written.is_some() {
+            common::committed(&commits, xid);
+        }
+
+        let read = common::read_object(&conn,
+                                       replica.as_ref(),
+                                       READ_SQL,
+                                       &o,
+                                       &mut read_histogram);
+        work.count(0, &read);
     }
 
-    (read_histogram, write_histogram)
+    (work, (read_histogram, write_histogram), WorkerMemory::read(&conn))
+}
+
+
+pub fn explain(explainer: &mut Explainer, conn: &Connection) {
+    common::explain_queries(explainer,
+                            conn,
+                            "manta_bucket_object",
+                            WRITE_SQL,
+                            READ_SQL,
+                            "manta_bucket_object_pkey");
+}
+
+
+pub fn lookups() -> Vec<String> {
+    vec![READ_SQL.to_string()]
+}
+
+
+pub fn count_rows(conn: &Connection) -> u64 {
+    work::count_rows(conn, "manta_bucket_object")
 }
 
 
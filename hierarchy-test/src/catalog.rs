@@ -0,0 +1,219 @@
+extern crate postgres;
+
+use pg_test_common::pipeline::{Client, Runtime};
+use postgres::Connection;
+use rand::thread_rng;
+
+use types::MantaObject;
+
+struct BackendMemory {
+    total_bytes: i64,
+    cache_bytes: i64,
+}
+
+/*
+ * Print the size of the catalogs that grow with every schema and table, and
+ * the size of the whole catalog of the connection's database. Every
+ * database carries its own copy of the catalog.
+ */
+pub fn print_catalog_sizes(conn: &Connection, phase: &str) {
+    let rows = conn
+        .query("SELECT current_database(), \
+                (SELECT count(*) FROM pg_class), pg_total_relation_size('pg_class'), \
+                (SELECT count(*) FROM pg_attribute), pg_total_relation_size('pg_attribute'), \
+                (SELECT count(*) FROM pg_namespace), pg_total_relation_size('pg_namespace'), \
+                (SELECT sum(pg_total_relation_size(c.oid))::bigint FROM pg_class c \
+                 JOIN pg_namespace n ON n.oid = c.relnamespace \
+                 WHERE n.nspname = 'pg_catalog' AND c.relkind = 'r')",
+               &[])
+        .unwrap();
+    let row = rows.get(0);
+    let database: String = row.get(0);
+    let class_rows: i64 = row.get(1);
+    let class_bytes: i64 = row.get(2);
+    let attribute_rows: i64 = row.get(3);
+    let attribute_bytes: i64 = row.get(4);
+    let namespace_rows: i64 = row.get(5);
+    let namespace_bytes: i64 = row.get(6);
+    let catalog_bytes: i64 = row.get(7);
+
+    report!("{} catalog of {}: {} bytes pg_class: {} rows {} bytes \
+              pg_attribute: {} rows {} bytes pg_namespace: {} rows {} bytes",
+             phase,
+             database,
+             catalog_bytes,
+             class_rows,
+             class_bytes,
+             attribute_rows,
+             attribute_bytes,
+             namespace_rows,
+             namespace_bytes);
+}
+
+// The databases created by create-vnode-databases.sh
+pub fn print_database_sizes(conn: &Connection, phase: &str) {
+    let rows = conn
+        .query("SELECT count(*), coalesce(sum(pg_database_size(oid)), 0)::bigint \
+                FROM pg_database WHERE datname LIKE 'manta\\_bucket\\_%'",
+               &[])
+        .unwrap();
+    let row = rows.get(0);
+    let count: i64 = row.get(0);
+    let bytes: i64 = row.get(1);
+
+    report!("{} databases: {} manta_bucket databases {} bytes ({} bytes/database)",
+             phase,
+             count,
+             bytes,
+             if count == 0 { 0 } else { bytes / count });
+}
+
+static VERSION_SQL: &str = "SELECT current_setting('server_version_num')::int";
+
+/*
+ * The memory of the querying backend. Cached relation and catalog entries
+ * live in CacheMemoryContext and the per index contexts beneath it.
+ * pg_backend_memory_contexts only exists in PostgreSQL 14 and later.
+ */
+static MEMORY_SQL: &str = "SELECT sum(total_bytes)::bigint, \
+                           (sum(total_bytes) FILTER (WHERE name = 'CacheMemoryContext' \
+                                                     OR parent = 'CacheMemoryContext'))::bigint \
+                           FROM pg_backend_memory_contexts";
+
+// The memory of the connection's own backend
+fn backend_memory(conn: &Connection) -> Option<BackendMemory> {
+    let server_version: i32 = conn.query(VERSION_SQL, &[]).ok()?.get(0).get(0);
+    if server_version < 140_000 {
+        return None;
+    }
+
+    let rows = conn.query(MEMORY_SQL, &[]).ok()?;
+    let row = rows.get(0);
+
+    Some(BackendMemory {
+        total_bytes: row.get(0),
+        cache_bytes: row.get(1),
+    })
+}
+
+// The same for a connection of the async engine
+fn client_memory(runtime: &Runtime, client: &Client) -> Option<BackendMemory> {
+    let server_version: i32 = runtime.block_on(client.query_one(VERSION_SQL, &[])).ok()?.get(0);
+    if server_version < 140_000 {
+        return None;
+    }
+
+    let row = runtime.block_on(client.query_one(MEMORY_SQL, &[])).ok()?;
+
+    Some(BackendMemory {
+        total_bytes: row.get(0),
+        cache_bytes: row.get(1),
+    })
+}
+
+/*
+ * The memory of the backends a mode's workers ran on. Each worker reads its
+ * own backend just before it finishes, so the memory reflects every target
+ * the worker touched with the settings it ran with.
+ */
+#[derive(Default)]
+pub struct WorkerMemory {
+    backends: i64,
+    total_bytes: i64,
+    cache_bytes: i64,
+    max_total_bytes: i64,
+    max_cache_bytes: i64,
+}
+
+impl WorkerMemory {
+    pub fn read(conn: &Connection) -> WorkerMemory {
+        WorkerMemory::from(backend_memory(conn))
+    }
+
+    pub fn read_async(runtime: &Runtime, client: &Client) -> WorkerMemory {
+        WorkerMemory::from(client_memory(runtime, client))
+    }
+
+    fn from(memory: Option<BackendMemory>) -> WorkerMemory {
+        match memory {
+            Some(memory) => WorkerMemory {
+                backends: 1,
+                total_bytes: memory.total_bytes,
+                cache_bytes: memory.cache_bytes,
+                max_total_bytes: memory.total_bytes,
+                max_cache_bytes: memory.cache_bytes,
+            },
+            None => WorkerMemory::default(),
+        }
+    }
+
+    pub fn merge(&mut self, other: &WorkerMemory) {
+        self.backends += other.backends;
+        self.total_bytes += other.total_bytes;
+        self.cache_bytes += other.cache_bytes;
+        self.max_total_bytes = self.max_total_bytes.max(other.max_total_bytes);
+        self.max_cache_bytes = self.max_cache_bytes.max(other.max_cache_bytes);
+    }
+
+    pub fn print(&self, phase: &str) {
+        if self.backends == 0 {
+            report!("{} worker backend memory: not reported by any worker (requires PostgreSQL 14 or later)", phase);
+            return;
+        }
+
+        report!("{} worker backend memory of {} backends: total: {} bytes mean {} bytes max \
+                 cache: {} bytes mean {} bytes max",
+                phase,
+                self.backends,
+                self.total_bytes / self.backends,
+                self.max_total_bytes,
+                self.cache_bytes / self.backends,
+                self.max_cache_bytes);
+    }
+}
+
+/*
+ * Run one object lookup for each statement in `lookups` from a single
+ * backend and report its memory afterwards. The first lookup also loads the
+ * catalog entries every lookup needs (types, operators and so on), so the
+ * growth per relation is measured over the lookups after it. That growth is
+ * the relcache and catcache cost of a backend touching another relation.
+ */
+pub fn print_backend_memory(conn: &Connection, phase: &str, lookups: &[String]) {
+    if backend_memory(conn).is_none() {
+        report!("{} backend memory: requires PostgreSQL 14 or later", phase);
+        return;
+    }
+
+    let mut rng = thread_rng();
+    let mut lookup = |sql: &String| {
+        let o = MantaObject::new(&mut rng);
+        conn.execute(sql.as_str(), &[&o.owner, &o.bucket_id, &o.name])
+            .unwrap();
+    };
+
+    let (first, rest) = match lookups.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    lookup(first);
+    let before = backend_memory(conn).unwrap();
+    rest.iter().for_each(lookup);
+    let after = backend_memory(conn).unwrap();
+
+    let growth = if rest.is_empty() {
+        String::new()
+    } else {
+        let growth = after.cache_bytes - before.cache_bytes;
+        format!(" (cache {:+} bytes over the last {} relations, {} bytes/relation)",
+                growth,
+                rest.len(),
+                growth / rest.len() as i64)
+    };
+    report!("{} backend memory after touching {} relations: total: {} bytes cache: {} bytes{}",
+            phase,
+            lookups.len(),
+            after.total_bytes,
+            after.cache_bytes,
+            growth);
+}
@@ -1,7 +1,76 @@
 use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
 
-use histogram::Histogram;
+use pg_test_common::histogram::Histogram;
+use pg_test_common::explain::Explainer;
+use pg_test_common::metrics;
+use pg_test_common::replica::{self, Lag};
+use pg_test_common::settings::Settings;
+use pg_test_common;
+use postgres::transaction::Transaction;
+use postgres::{self, Connection};
+use rand::thread_rng;
 
+use types::MantaObject;
+use APP;
+
+// A worker's connection, tagged with the utility's application_name
+pub fn connect(url: &str) -> Connection {
+    pg_test_common::connect(url, APP)
+}
+
+pub fn connect_replica(url: &str) -> Connection {
+    replica::connect(url, APP)
+}
+
+// Where a thread sends its writes for the checker, when reads go to a replica
+pub type Commits = Option<Sender<(i64, Instant)>>;
+
+/*
+ * Start checking the writes of a mode's threads on the replica, if reads go
+ * to one. Visibility is the same in every database of the standby, so in
+ * database mode the checker uses the one the URL connects to by default.
+ */
+pub fn spawn_checker(replica_url: &Option<Arc<String>>,
+                     settings: &Settings) -> (Commits, Option<JoinHandle<Lag>>) {
+    match *replica_url {
+        Some(ref url) => {
+            let replica = connect_replica(url);
+            settings.apply(&replica);
+            let (commits, checker) = replica::spawn_checker(replica);
+            (Some(commits), Some(checker))
+        },
+        None => (None, None),
+    }
+}
+
+// The lag the checker measured, once every thread has dropped its sender
+pub fn join_checker(checker: Option<JoinHandle<Lag>>) -> Lag {
+    match checker.map(|checker| checker.join()) {
+        Some(Ok(lag)) => lag,
+        Some(Err(_)) => {
+            report!("replica checker thread panicked");
+            Lag::default()
+        },
+        None => Lag::default(),
+    }
+}
+
+// The id of a write's transaction, for the checker to look for on the replica
+pub fn txid(trans: &Transaction) -> postgres::Result<i64> {
+    Ok(trans.query("SELECT txid_current()", &[])?.get(0).get(0))
+}
+
+// Hand a write whose transaction `xid` has just committed to the checker
+pub fn committed(commits: &Commits, xid: Option<i64>) {
+    if let (Some(ref commits), Some(xid)) = (commits, xid) {
+        // The checker only goes away if it panicked, which join_checker reports
+        let _ = commits.send((xid, Instant::now()));
+    }
+}
 
 pub fn sharks() -> HashMap<String, Option<String>> {
     let mut headers = HashMap::new();
@@ -23,20 +92,71 @@ pub fn headers() -> HashMap<String, Option<String>> {
     headers
 }
 
-pub fn print_results(read: &Histogram, write: &Histogram) -> () {
-    println!(
+pub fn print_results(read: &Histogram, write: &Histogram) {
+    report!(
         "Read Latency Percentiles: p50: {} ns p90: {} ns p99: {} ns p999: {}",
-        read.percentile(50.0).unwrap(),
-        read.percentile(90.0).unwrap(),
-        read.percentile(99.0).unwrap(),
-        read.percentile(99.9).unwrap(),
+        read.percentile(50.0),
+        read.percentile(90.0),
+        read.percentile(99.0),
+        read.percentile(99.9),
     );
 
-    println!(
+    report!(
         "Write Latency Percentiles: p50: {} ns p90: {} ns p99: {} ns p999: {}",
-        write.percentile(50.0).unwrap(),
-        write.percentile(90.0).unwrap(),
-        write.percentile(99.0).unwrap(),
-        write.percentile(99.9).unwrap(),
+        write.percentile(50.0),
+        write.percentile(90.0),
+        write.percentile(99.0),
+        write.percentile(99.9),
     );
 }
+
+/*
+ * Explain an object insert and lookup against one hierarchy target. The
+ * lookup is expected to use the primary key of the target's
+ * manta_bucket_object table.
+ */
+pub fn explain_queries(explainer: &mut Explainer,
+                       conn: &Connection,
+                       target: &str,
+                       write_sql: &str,
+                       read_sql: &str,
+                       pkey: &str) {
+    let o = MantaObject::new(&mut thread_rng());
+
+    explainer.explain(conn,
+                      &[target, " insert"].concat(),
+                      write_sql,
+                      &[
+                          &o.id,
+                          &o.owner,
+                          &o.bucket_id,
+                          &o.name,
+                          &o.vnode,
+                          &o.content_length,
+                          &o.content_md5,
+                          &o.content_type,
+                          &o.headers,
+                          &o.sharks
+                      ],
+                      &[]);
+    explainer.explain(conn,
+                      &[target, " lookup"].concat(),
+                      read_sql,
+                      &[&o.owner, &o.bucket_id, &o.name],
+                      &[pkey]);
+}
+
+// Look up the object a thread just wrote, on the replica when there is one
+pub fn read_object(conn: &Connection,
+                   replica: Option<&Connection>,
+                   read_sql: &str,
+                   o: &MantaObject,
+                   histogram: &mut Histogram) -> Option<u64> {
+    let read_conn = replica.unwrap_or(conn);
+    metrics::timed("Read", histogram, || {
+        let read_trans = read_conn.transaction()?;
+        let rows = read_trans.execute(read_sql, &[&o.owner, &o.bucket_id, &o.name])?;
+        read_trans.commit()?;
+        Ok(rows)
+    })
+}
@@ -0,0 +1,317 @@
+#[macro_use]
+extern crate clap;
+extern crate futures;
+#[macro_use]
+extern crate pg_test_common;
+extern crate postgres;
+extern crate rand;
+extern crate uuid;
+extern crate uuid1;
+
+mod baseline;
+mod catalog;
+mod common;
+mod database;
+mod opts;
+mod pipelined;
+mod schema;
+mod table;
+mod types;
+
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{App, ArgMatches, Error, ErrorKind, SubCommand};
+use pg_test_common::cli::{self, Options};
+use pg_test_common::explain::Explainer;
+use pg_test_common::metrics;
+use pg_test_common::results::Results;
+use pg_test_common::settings::Settings;
+use pg_test_common::sweep::Sweep;
+use pg_test_common::work;
+use pg_test_common::{statements, stats};
+use postgres::{Connection, TlsMode};
+
+pub use opts::{Engine, Mode};
+
+/*
+ * This program was written to look at performance of querying across the three
+ * different levels of postgres data hierarchy (i.e. databases, schemas, and
+ * tables).
+ *
+ * The create-vnode-schemas.sh script can be used to facilitate creation of
+ * the schemas.
+ *
+ *     ./create-vnode-schemas.sh {1..10000}
+ */
+
+static APP: &str = "hierarchy-test";
+const THREAD_COUNT: u32 = 16;
+const THREAD_ITERATIONS: u32 = 1000;
+const DEFAULT_HIERARCHY_COUNT: u32 = 10000;
+const CONNECTION_COUNT: u32 = 4;
+
+
+fn phase_name(mode: &Mode) -> &'static str {
+    match *mode {
+        Mode::Baseline => "Baseline mode",
+        Mode::Database => "Database mode",
+        Mode::Schema => "Schema mode",
+        Mode::Table => "Table mode",
+    }
+}
+
+
+/*
+ * Capture the plans of an object insert and lookup against every hierarchy
+ * target of the mode before it runs.
+ */
+fn explain_mode(dir: &str, mode: &Mode, url: &str, conn: &Connection, hierarchy_count: u32) {
+    let phase = phase_name(mode);
+    let mut explainer = Explainer::new(dir, APP, phase);
+
+    match *mode {
+        Mode::Baseline => baseline::explain(&mut explainer, conn),
+        Mode::Database => database::explain(&mut explainer, url, hierarchy_count),
+        Mode::Schema => schema::explain(&mut explainer, conn, hierarchy_count),
+        Mode::Table => table::explain(&mut explainer, conn, hierarchy_count),
+    }
+
+    explainer.finish();
+}
+
+/*
+ * Report what the mode's hierarchy costs the catalog, and how much memory a
+ * fresh backend, tagged and set up like the workers, needs once it has
+ * touched every target it can reach. A backend is bound to a single
+ * database, so in database mode only the workers' own backends are measured.
+ */
+fn report_catalog(mode: &Mode, url: &str, conn: &Connection, hierarchy_count: u32, settings: &Settings) {
+    let phase = phase_name(mode);
+    catalog::print_catalog_sizes(conn, phase);
+
+    let lookups = match *mode {
+        Mode::Baseline => baseline::lookups(),
+        Mode::Database => {
+            catalog::print_database_sizes(conn, phase);
+            let probe = common::connect(&[url, "/manta_bucket_1"].concat());
+            catalog::print_catalog_sizes(&probe, phase);
+            return;
+        },
+        Mode::Schema => schema::lookups(hierarchy_count),
+        Mode::Table => table::lookups(hierarchy_count),
+    };
+
+    let probe = common::connect(url);
+    settings.apply(&probe);
+    catalog::print_backend_memory(&probe, phase, &lookups);
+}
+
+
+// The manta_bucket_object rows in every hierarchy target of the mode
+fn count_objects(mode: &Mode, url: &str, conn: &Connection, hierarchy_count: u32) -> u64 {
+    match *mode {
+        Mode::Baseline => baseline::count_rows(conn),
+        Mode::Database => database::count_rows(url, hierarchy_count),
+        Mode::Schema => schema::count_rows(conn, hierarchy_count),
+        Mode::Table => table::count_rows(conn, hierarchy_count),
+    }
+}
+
+
+/*
+ * What to run: the mode, the thread and hierarchy counts to sweep over, the
+ * iterations each thread makes and the options shared with the other
+ * utilities. With the async engine the threads are tasks sharing
+ * `connections` connections.
+ */
+pub struct Config {
+    pub url: String,
+    pub mode: Mode,
+    pub engine: Engine,
+    pub connections: u32,
+    // Reads go to this standby instead of the primary
+    pub replica_url: Option<String>,
+    pub threads: Vec<u32>,
+    pub iterations: u32,
+    pub hierarchy: Vec<u32>,
+    pub options: Options,
+}
+
+impl Config {
+    // The matches of subcommand() or app()
+    pub fn from_matches(matches: &ArgMatches) -> Config {
+        let options = Options::from_matches(matches, APP);
+        let scenario = &options.scenario;
+        let mode = value_t!(matches, "mode", Mode).unwrap_or_else(|e| e.exit());
+        let hierarchy = |name| cli::sweep_counts(matches, name, None, DEFAULT_HIERARCHY_COUNT);
+        let engine = value_t!(matches, "engine", Engine).unwrap_or(Engine::Sync);
+        if engine == Engine::Async && matches.is_present("replicaUrl") {
+            Error::with_description("--replica-url is only supported by the sync engine",
+                                    ErrorKind::ArgumentConflict)
+                .exit();
+        }
+
+        Config {
+            url: cli::url(matches, scenario),
+            threads: cli::sweep_counts(matches, "threads", scenario.threads.as_ref(), THREAD_COUNT),
+            iterations: cli::parsed(matches, "iterations")
+                .or(scenario.iterations)
+                .unwrap_or(THREAD_ITERATIONS),
+            hierarchy: match mode {
+                Mode::Baseline => vec![1],
+                Mode::Database => hierarchy("databaseCount"),
+                Mode::Schema => hierarchy("schemaCount"),
+                Mode::Table => hierarchy("tableCount"),
+            },
+            mode,
+            engine,
+            connections: cli::parsed(matches, "connections").unwrap_or(CONNECTION_COUNT),
+            replica_url: cli::string(matches, "replicaUrl"),
+            options,
+        }
+    }
+}
+
+pub fn subcommand<'a, 'b>(name: &str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about("Tool to test different hierarchy options offered by PostgreSQL")
+        .args(&cli::args("Iterations per thread (Default: 1000)"))
+        .args(&opts::args())
+}
+
+// The command line of hierarchy-test itself
+pub fn app<'a, 'b>() -> App<'a, 'b> {
+    subcommand(APP).version(crate_version!())
+}
+
+
+pub fn run(config: Config) {
+    let options = &config.options;
+    let mode = config.mode;
+    let url_arc = Arc::new(config.url.clone());
+    let replica_arc = config.replica_url.clone().map(Arc::new);
+    let settings_arc = Arc::new(options.settings.clone());
+    let thread_iterations = config.iterations;
+    let trials = options.trials;
+    let mut sweep = Sweep::new();
+    sweep.add("threads", config.threads.clone());
+    sweep.add("hierarchy", config.hierarchy.clone());
+
+    let conn = Connection::connect(url_arc.as_str(), TlsMode::None)
+        .unwrap_or_else(|e| {
+            eprintln!("Postgres connection error: {}", e);
+            process::exit(1)
+        });
+    options.settings.check(&conn);
+
+    let phase = phase_name(&mode);
+    let mut results = Results::new(APP, &options.settings);
+    let points = sweep.points();
+    for (p, point) in points.iter().enumerate() {
+        let thread_count = point.get("threads");
+        let hierarchy_count = point.get("hierarchy");
+        // The databases the workers' statements run in, other than the connection's own
+        let databases = match mode {
+            Mode::Database => database::names(hierarchy_count),
+            _ => Vec::new(),
+        };
+        if sweep.is_sweep() {
+            report!("Sweep point {} of {}: {}", p + 1, points.len(), point.describe());
+            results.set_point(point);
+        }
+        if let Some(ref dir) = options.explain_dir {
+            explain_mode(dir, &mode, &url_arc, &conn, hierarchy_count);
+        }
+
+        for trial in 0..trials {
+            if trials > 1 {
+                report!("Trial {} of {}", trial + 1, trials);
+            }
+            if p > 0 || trial > 0 {
+                thread::sleep(Duration::from_secs(1));
+            }
+
+            let statements_tracked = statements::reset(&conn, &databases);
+            let stats_before = stats::Snapshot::take(&conn);
+
+            let objects_before = count_objects(&mode, &url_arc, &conn, hierarchy_count);
+            metrics::start_phase(mode.arg(), &results.label(phase));
+            let start = Instant::now();
+            let (work, (read_histogram, write_histogram), lag, memory) = match mode {
+                _ if config.engine == Engine::Async => pipelined::run_tasks(&mode,
+                                                                            &url_arc,
+                                                                            thread_count,
+                                                                            thread_iterations,
+                                                                            hierarchy_count,
+                                                                            config.connections,
+                                                                            &settings_arc),
+                Mode::Baseline => baseline::run_threads(url_arc.clone(),
+                                                        &thread_count,
+                                                        Arc::new(thread_iterations),
+                                                        replica_arc.clone(),
+                                                        settings_arc.clone()),
+                Mode::Database => database::run_threads(url_arc.clone(),
+                                                        &thread_count,
+                                                        Arc::new(thread_iterations),
+                                                        Arc::new(hierarchy_count),
+                                                        replica_arc.clone(),
+                                                        settings_arc.clone()),
+                Mode::Schema => schema::run_threads(url_arc.clone(),
+                                                    &thread_count,
+                                                    Arc::new(thread_iterations),
+                                                    Arc::new(hierarchy_count),
+                                                    replica_arc.clone(),
+                                                    settings_arc.clone()),
+                Mode::Table => table::run_threads(url_arc.clone(),
+                                                  &thread_count,
+                                                  Arc::new(thread_iterations),
+                                                  Arc::new(hierarchy_count),
+                                                  replica_arc.clone(),
+                                                  settings_arc.clone()),
+            };
+            let end = Instant::now();
+            report!("{} duration: {:?}", phase, end.duration_since(start));
+            if config.engine == Engine::Async {
+                report!("{} statements: autocommit, without the BEGIN and COMMIT around each \
+                         of the sync engine", phase);
+            }
+            work.print(phase);
+            memory.print(phase);
+            if replica_arc.is_some() {
+                lag.print(phase);
+            }
+            work::verify_rows(phase,
+                              "manta_bucket_object",
+                              objects_before,
+                              count_objects(&mode, &url_arc, &conn, hierarchy_count),
+                              &work);
+            stats::print_delta(phase, &stats_before, &stats::Snapshot::take_settled(&conn));
+            if statements_tracked {
+                statements::print_top(&conn, phase, &databases);
+            }
+            // The catalog doesn't change between trials
+            if trial == 0 {
+                report_catalog(&mode, &url_arc, &conn, hierarchy_count, &options.settings);
+            }
+
+            let mut latencies = vec![("Read", &read_histogram), ("Write", &write_histogram)];
+            if replica_arc.is_some() {
+                latencies.push(("Replica lag", &lag.visible_after));
+            }
+            results.add_phase(phase, write_histogram.entries(), end.duration_since(start), &latencies);
+
+            match mode {
+                Mode::Baseline => baseline::delete_table(&conn),
+                Mode::Database => database::delete_tables(url_arc.clone(), hierarchy_count),
+                Mode::Schema => schema::delete_tables(&conn, hierarchy_count),
+                Mode::Table => table::delete_tables(&conn, hierarchy_count),
+            }
+        }
+    }
+
+    options.output.finish(&results);
+    metrics::finish();
+}
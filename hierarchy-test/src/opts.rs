@@ -1,10 +1,9 @@
-extern crate clap;
-
 use std::str::FromStr;
 
-use clap::{App, Arg, ArgMatches};
-
+use clap::Arg;
+use pg_test_common::cli;
 
+#[derive(Clone, Copy)]
 pub enum Mode {
     Baseline,
     Database,
@@ -12,6 +11,18 @@ pub enum Mode {
     Table
 }
 
+impl Mode {
+    // The name --mode takes
+    pub fn arg(&self) -> &'static str {
+        match *self {
+            Mode::Baseline => "baseline",
+            Mode::Database => "database",
+            Mode::Schema => "schema",
+            Mode::Table => "table",
+        }
+    }
+}
+
 impl FromStr for Mode {
     type Err = &'static str;
 
@@ -26,46 +37,70 @@ impl FromStr for Mode {
     }
 }
 
-pub fn parse<'a, 'b>(app: String) -> ArgMatches<'a> {
-    App::new(app)
-        .about("Tool to test different hierarchy options offered by PostgreSQL")
-        .version(crate_version!())
-        .arg(Arg::with_name("mode")
-             .help("Mode of operation")
-             .long("mode")
-             .short("m")
-             .takes_value(true)
-             .required(true)
-             .possible_values(&["baseline", "database", "schema", "table"]))
-        .arg(Arg::with_name("threadIterations")
-             .help("Iterations per thread (Default: 1000)")
-             .short("i")
-             .long("iterations")
-             .takes_value(true))
-        .arg(Arg::with_name("threadCount")
-             .help("Thread count (Default: 16)")
-             .short("t")
-             .long("thread-count")
-             .takes_value(true))
-        .arg(Arg::with_name("schemaCount")
-             .help("Number of schemas to use for test (Default: 10000)")
-             .short("s")
-             .long("schema-count")
-             .takes_value(true))
-        .arg(Arg::with_name("databaseCount")
-             .help("Number of databases to use for test (Default: 10000)")
-             .short("d")
-             .long("database-count")
-             .takes_value(true))
-        .arg(Arg::with_name("tableCount")
-             .help("Number of tables to use for test (Default: 10000)")
-             .long("table-count")
-             .takes_value(true))
-        .arg(Arg::with_name("url")
-             .help("Postgres URL")
-             .short("u")
-             .long("url")
-             .takes_value(true)
-             .required(true))
-        .get_matches()
+// How the mode's threads are run
+#[derive(Clone, Copy, PartialEq)]
+pub enum Engine {
+    // A thread and a connection each, with the postgres crate
+    Sync,
+    // Tasks sharing a few pipelined connections, with tokio-postgres
+    Async,
+}
+
+impl FromStr for Engine {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sync"  => Ok(Engine::Sync),
+            "async" => Ok(Engine::Async),
+            _       => Err("invalid engine")
+        }
+    }
+}
+
+// The options only hierarchy-test takes
+pub fn args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("mode")
+            .help("Mode of operation")
+            .long("mode")
+            .short("m")
+            .takes_value(true)
+            .required(true)
+            .possible_values(&["baseline", "database", "schema", "table"]),
+        Arg::with_name("schemaCount")
+            .help("Number of schemas to use for test, or a list to sweep (Default: 10000)")
+            .short("s")
+            .long("schema-count")
+            .takes_value(true)
+            .validator(cli::counts),
+        Arg::with_name("databaseCount")
+            .help("Number of databases to use for test, or a list to sweep (Default: 10000)")
+            .short("d")
+            .long("database-count")
+            .takes_value(true)
+            .validator(cli::counts),
+        Arg::with_name("tableCount")
+            .help("Number of tables to use for test, or a list to sweep (Default: 10000)")
+            .long("table-count")
+            .takes_value(true)
+            .validator(cli::counts),
+        Arg::with_name("engine")
+            .help("Run the threads as OS threads with a connection each, or as tasks of the async engine (Default: sync)")
+            .long("engine")
+            .value_name("ENGINE")
+            .takes_value(true)
+            .possible_values(&["sync", "async"]),
+        Arg::with_name("replicaUrl")
+            .help("Send reads to this standby and measure how far behind the primary it is")
+            .long("replica-url")
+            .value_name("URL")
+            .takes_value(true),
+        Arg::with_name("connections")
+            .help("Connections the async engine spreads its tasks over (Default: 4)")
+            .long("connections")
+            .value_name("COUNT")
+            .takes_value(true)
+            .validator(cli::count),
+    ]
 }
@@ -0,0 +1,170 @@
+extern crate futures;
+extern crate uuid1;
+
+use std::process;
+
+use futures::future::{self, FutureExt};
+use futures::stream::{self, StreamExt};
+use pg_test_common::histogram::Histogram;
+use pg_test_common::pipeline::{self, Client, Params};
+use pg_test_common::replica::Lag;
+use pg_test_common::settings::Settings;
+use pg_test_common::work::Work;
+use rand::{thread_rng, Rng};
+use uuid::Uuid;
+
+use baseline;
+use catalog::WorkerMemory;
+use common;
+use database;
+use opts::Mode;
+use schema;
+use table;
+use types::{HistogramPair, MantaObject};
+use APP;
+
+
+/*
+ * Run the mode's object inserts and lookups as `task_count` concurrent tasks
+ * spread over `connection_count` connections, all driven from this thread by
+ * the async engine. Each task writes and then reads back an object in the
+ * target it picked, just like a thread of the synchronous engine, and the
+ * tasks sharing a connection have their statements pipelined.
+ *
+ * A backend is bound to a single database, so in database mode every task of
+ * a connection uses the database the connection picked.
+ *
+ * Unlike a thread, which wraps each write and read in a transaction of its
+ * own, a task runs them as autocommit statements: the tasks sharing a
+ * connection interleave their statements, so none of them can hold a
+ * transaction open on it. A task's write and read each save the round trips
+ * of BEGIN and COMMIT.
+ */
+pub fn run_tasks(mode: &Mode,
+                 url: &str,
+                 task_count: u32,
+                 iterations: u32,
+                 hierarchy_count: u32,
+                 connection_count: u32,
+                 settings: &Settings) -> (Work, HistogramPair, Lag, WorkerMemory) {
+    let runtime = pipeline::runtime();
+    let connection_count = connection_count.min(task_count);
+    let mut rng = thread_rng();
+
+    // Each connection along with the database it is bound to in database mode
+    let clients: Vec<(Client, u32)> = match *mode {
+        Mode::Database => (0..connection_count)
+            .map(|_| {
+                let db = rng.gen_range(1, hierarchy_count + 1);
+                let url_with_db = [url, "/manta_bucket_", &db.to_string()].concat();
+                (pipeline::connect(&runtime, &url_with_db, APP, 1).remove(0), db)
+            })
+            .collect(),
+        _ => pipeline::connect(&runtime, url, APP, connection_count)
+            .into_iter()
+            .map(|client| (client, 0))
+            .collect(),
+    };
+    for (client, _) in &clients {
+        settings.apply_async(&runtime, client);
+    }
+
+    // The connection and statements of each task, prepared before it starts
+    let tasks: Vec<(&Client, String, String)> = (0..task_count as usize)
+        .map(|task| {
+            let (ref client, db) = clients[task % clients.len()];
+            let (write_sql, read_sql) = match *mode {
+                Mode::Baseline => (baseline::WRITE_SQL.to_string(), baseline::READ_SQL.to_string()),
+                Mode::Database => (database::write_sql(db), database::read_sql(db)),
+                Mode::Schema => {
+                    let schema = rng.gen_range(1, hierarchy_count + 1);
+                    (schema::write_sql(schema), schema::read_sql(schema))
+                },
+                Mode::Table => {
+                    let table = rng.gen_range(1, hierarchy_count + 1);
+                    let table_name = ["manta_bucket_object_", &table.to_string()].concat();
+                    (table::write_sql(&table_name), table::read_sql(&table_name))
+                },
+            };
+            (client, write_sql, read_sql)
+        })
+        .collect();
+    let prepares = tasks.iter().map(|&(client, ref write_sql, ref read_sql)| {
+        future::try_join(client.prepare(write_sql), client.prepare(read_sql))
+    });
+    let statements: Vec<_> = runtime
+        .block_on(future::try_join_all(prepares))
+        .unwrap_or_else(|e| {
+            eprintln!("unable to prepare the {} statements: {}", mode.arg(), e);
+            process::exit(1)
+        });
+
+    let runs = tasks.iter().zip(&statements).map(|(&(client, _, _), (write, read))| {
+        stream::iter(0..iterations).fold(
+            (Work::thread(), (Histogram::new(), Histogram::new())),
+            move |(mut work, (mut read_histogram, mut write_histogram)), _| {
+                let o = MantaObject::new(&mut thread_rng());
+                let read_params = lookup_params(&o);
+                pipeline::timed("Write", client.execute_raw(write, object_params(o))).then(move |written| {
+                    pipeline::record(&mut work, &mut write_histogram, 1, written);
+                    pipeline::timed("Read", client.execute_raw(read, read_params)).map(move |found| {
+                        pipeline::record(&mut work, &mut read_histogram, 0, found);
+                        (work, (read_histogram, write_histogram))
+                    })
+                })
+            })
+    });
+
+    let mut work = Work::default();
+    let mut read_histogram = Histogram::new();
+    let mut write_histogram = Histogram::new();
+    for (task_work, (task_read_hist, task_write_hist)) in runtime.block_on(future::join_all(runs)) {
+        work.add(&task_work);
+        read_histogram.merge(&task_read_hist);
+        write_histogram.merge(&task_write_hist);
+    }
+
+    common::print_results(&read_histogram, &write_histogram);
+
+    // Each connection's backend served every task that shared it
+    let mut memory = WorkerMemory::default();
+    for (client, _) in &clients {
+        memory.merge(&WorkerMemory::read_async(&runtime, client));
+    }
+
+    // Reads aren't sent to a replica by the async engine
+    (work, (read_histogram, write_histogram), Lag::default(), memory)
+}
+
+
+// tokio-postgres takes the uuid crate's current version
+fn uuid(id: &Uuid) -> uuid1::Uuid {
+    uuid1::Uuid::from_bytes(*id.as_bytes())
+}
+
+
+// The parameters of the mode's write_sql
+fn object_params(o: MantaObject) -> Params {
+    vec![
+        pipeline::param(uuid(&o.id)),
+        pipeline::param(uuid(&o.owner)),
+        pipeline::param(uuid(&o.bucket_id)),
+        pipeline::param(o.name),
+        pipeline::param(o.vnode),
+        pipeline::param(o.content_length),
+        pipeline::param(o.content_md5),
+        pipeline::param(o.content_type),
+        pipeline::param(o.headers),
+        pipeline::param(o.sharks),
+    ]
+}
+
+
+// The parameters of the mode's read_sql
+fn lookup_params(o: &MantaObject) -> Params {
+    vec![
+        pipeline::param(uuid(&o.owner)),
+        pipeline::param(uuid(&o.bucket_id)),
+        pipeline::param(o.name.clone()),
+    ]
+}
@@ -1,126 +1,189 @@
-extern crate histogram;
 extern crate postgres;
 extern crate rand;
 extern crate uuid;
 
 use std::sync::Arc;
 use std::thread;
-use std::time::Instant;
 
-use histogram::Histogram;
-use postgres::{Connection, TlsMode};
+use pg_test_common::histogram::Histogram;
+use pg_test_common::metrics;
+use pg_test_common::replica::Lag;
+use pg_test_common::settings::Settings;
+use pg_test_common::explain::Explainer;
+use pg_test_common::work::{self, Work};
+use postgres::Connection;
 use rand::{thread_rng, Rng};
 
-use common;
+use catalog::WorkerMemory;
+use common::{self, Commits};
 use types::{HistogramPair, MantaObject};
 
 
 pub fn run_threads(url: Arc<String>,
                    thread_count: &u32,
                    thread_iterations: Arc<u32>,
-                   table_count: Arc<u32>) {
+                   table_count: Arc<u32>,
+                   replica_url: Option<Arc<String>>,
+                   settings: Arc<Settings>) -> (Work, HistogramPair, Lag, WorkerMemory) {
+    let (commits, checker) = common::spawn_checker(&replica_url, &settings);
     let mut handles = Vec::new();
 
+    let mut work = Work::default();
+    let mut memory = WorkerMemory::default();
     let mut read_histogram = Histogram::new();
     let mut write_histogram = Histogram::new();
 
-    for _number in 1..*thread_count {
+    for _number in 0..*thread_count {
         let url_clone = Arc::clone(&url);
         let thread_iterations_clone = Arc::clone(&thread_iterations);
         let table_count_clone = Arc::clone(&table_count);
+        let replica_url_clone = replica_url.clone();
+        let commits_clone = commits.clone();
+        let settings_clone = Arc::clone(&settings);
         let h = thread::spawn(|| multiple_database_queries(url_clone,
                                                            thread_iterations_clone,
-                                                           table_count_clone));
+                                                           table_count_clone,
+                                                           replica_url_clone,
+                                                           commits_clone,
+                                                           settings_clone));
         handles.push(h);
     }
 
+    // The checker finishes once every thread has dropped its sender
+    drop(commits);
+
     for handle in handles {
         match handle.join() {
-            Ok((thread_read_hist, thread_write_hist)) => {
+            Ok((thread_work, (thread_read_hist, thread_write_hist), thread_memory)) => {
+                work.add(&thread_work);
+                memory.merge(&thread_memory);
                 read_histogram.merge(&thread_read_hist);
                 write_histogram.merge(&thread_write_hist);
             }
-            Err(_) => println!("database thread panicked"),
+            Err(_) => report!("database thread panicked"),
         }
     }
 
+    let lag = common::join_checker(checker);
     common::print_results(&read_histogram, &write_histogram);
+
+    (work, (read_histogram, write_histogram), lag, memory)
 }
 
 
 fn multiple_database_queries(url: Arc<String>,
                              thread_iterations: Arc<u32>,
-                             table_count: Arc<u32>) -> HistogramPair {
+                             table_count: Arc<u32>,
+                             replica_url: Option<Arc<String>>,
+                             commits: Commits,
+                             settings: Arc<Settings>) -> (Work, HistogramPair, WorkerMemory) {
     let mut rng = thread_rng();
-    let table = (rng.gen::<u32>() + 1) % *table_count;
+    let table = rng.gen_range(1, *table_count + 1);
     let table_name = ["manta_bucket_object_", &table.to_string()].concat();
 
+    let mut work = Work::thread();
     let mut read_histogram = Histogram::new();
     let mut write_histogram = Histogram::new();
-    let conn = Connection::connect(&*url.as_str(), TlsMode::None).unwrap();
-
-    for _number in 1..*thread_iterations {
+    let conn = common::connect(&url);
+    settings.apply(&conn);
+    let replica = replica_url.map(|replica_url| {
+        let replica = common::connect_replica(&replica_url);
+        settings.apply(&replica);
+        replica
+    });
+
+    for _number in 0..*thread_iterations {
         let o = MantaObject::new(&mut rng);
 
-        let write_start = Instant::now();
-        let write_trans = conn.transaction().unwrap();
-        let write_sql = ["INSERT INTO ",
-                         &table_name,
-                         &" (id, owner, bucket_id, name, vnode, \
-                           content_length, content_md5, content_type, headers, sharks) \
-                           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"].concat();
-
-        write_trans.execute(write_sql.as_str(),
-                            &[
-                                &o.id,
-                                &o.owner,
-                                &o.bucket_id,
-                                &o.name,
-                                &o.vnode,
-                                &o.content_length,
-                                &o.content_md5,
-                                &o.content_type,
-                                &o.headers,
-                                &o.sharks
-                            ]).unwrap();
-
-        write_trans.commit().unwrap();
-        let write_end = Instant::now();
-
-        let write_duration = write_end.duration_since(write_start);
-        let write_nanos = write_duration.as_secs() * 1_000_000_000
-            + write_duration.subsec_nanos() as u64;
-        write_histogram.increment(write_nanos).unwrap();
-
-        let read_start = Instant::now();
-        let read_trans = conn.transaction().unwrap();
-        let read_sql = ["SELECT * FROM ",
-                        &table_name,
-                        &" WHERE owner = $1 AND bucket_id = $2 \
-                          AND name = $3"].concat();
-
-        read_trans
-            .execute(read_sql.as_str(), &[&o.owner, &o.bucket_id, &o.name])
-            .unwrap();
-
-        read_trans.commit().unwrap();
-        let read_end = Instant::now();
-
-        let read_duration = read_end.duration_since(read_start);
-        let read_nanos = read_duration.as_secs() * 1_000_000_000
-            + read_duration.subsec_nanos() as u64;
-        read_histogram.increment(read_nanos).unwrap();
+        let write_sql = write_sql(&table_name);
+        let mut xid = None;
+        let written = metrics::timed("Write", &mut write_histogram, || {
+            let write_trans = conn.transaction()?;
+            write_trans.execute(write_sql.as_str(),
+                                &[
+                                    &o.id,
+                                    &o.owner,
+                                    &o.bucket_id,
+                                    &o.name,
+                                    &o.vnode,
+                                    &o.content_length,
+                                    &o.content_md5,
+                                    &o.content_type,
+                                    &o.headers,
+                                    &o.sharks
+                                ])?;
+            if commits.is_some() {
+                xid = Some(common::txid(&write_trans)?);
+            }
+            write_trans.commit()
+        });
+        work.count(1, &written);
+        if written.is_some() {
+            common::committed(&commits, xid);
+        }
+
+        let read_sql = read_sql(&table_name);
+        let read = common::read_object(&conn,
+                                       replica.as_ref(),
+                                       read_sql.as_str(),
+                                       &o,
+                                       &mut read_histogram);
+        work.count(0, &read);
+    }
+
+    (work, (read_histogram, write_histogram), WorkerMemory::read(&conn))
+}
+
+
+pub fn write_sql(table_name: &str) -> String {
+    ["INSERT INTO ",
+     table_name,
+     " (id, owner, bucket_id, name, vnode, \
+       content_length, content_md5, content_type, headers, sharks) \
+       VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"].concat()
+}
+
+
+pub fn read_sql(table_name: &str) -> String {
+    ["SELECT * FROM ",
+     table_name,
+     " WHERE owner = $1 AND bucket_id = $2 \
+       AND name = $3"].concat()
+}
+
+
+pub fn explain(explainer: &mut Explainer, conn: &Connection, table_count: u32) {
+    for number in 1..=table_count {
+        let table_name = ["manta_bucket_object_", &number.to_string()].concat();
+        common::explain_queries(explainer,
+                                conn,
+                                &table_name,
+                                &write_sql(&table_name),
+                                &read_sql(&table_name),
+                                &[&table_name, "_pkey"].concat());
     }
+}
+
+
+pub fn lookups(table_count: u32) -> Vec<String> {
+    (1..=table_count)
+        .map(|number| read_sql(&["manta_bucket_object_", &number.to_string()].concat()))
+        .collect()
+}
+
 
-    (read_histogram, write_histogram)
+pub fn count_rows(conn: &Connection, table_count: u32) -> u64 {
+    (1..=table_count)
+        .map(|number| work::count_rows(conn, &["manta_bucket_object_", &number.to_string()].concat()))
+        .sum()
 }
 
 
 pub fn delete_tables(conn: &Connection, table_count: u32) {
-    for number in 1..table_count {
+    for number in 1..=table_count {
         let trans = conn.transaction().unwrap();
         let delete_sql = ["DELETE FROM ",
-                          &"manta_bucket_object_",
+                          "manta_bucket_object_",
                           &number.to_string()].concat();
         trans.execute(delete_sql.as_str(), &[]).unwrap();
         trans.commit().unwrap();
@@ -1,15 +1,14 @@
-extern crate histogram;
-
 use std::collections::HashMap;
 use std::iter;
 
+use pg_test_common::histogram::Histogram;
 use rand::distributions::Alphanumeric;
 use rand::{Rng, ThreadRng};
 use uuid::Uuid;
 
 use common;
 
-pub type HistogramPair = (histogram::Histogram, histogram::Histogram);
+pub type HistogramPair = (Histogram, Histogram);
 
 pub struct MantaObject {
     pub id             : Uuid,
@@ -32,7 +31,7 @@ impl MantaObject {
             .collect();
 
         MantaObject {
-            name: name,
+            name,
             id: Uuid::new_v4(),
             bucket_id: Uuid::new_v4(),
             vnode: 1000,
@@ -0,0 +1,548 @@
+extern crate clap;
+extern crate futures;
+#[macro_use]
+extern crate pg_test_common;
+extern crate postgres;
+extern crate uuid;
+extern crate uuid1;
+
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration,Instant};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures::future;
+use futures::stream::{self, StreamExt};
+use pg_test_common::cli::{self, Options};
+use pg_test_common::durability::{self, Comparison, Durability};
+use pg_test_common::histogram::Histogram;
+use pg_test_common::metrics;
+use pg_test_common::explain::Explainer;
+use pg_test_common::pipeline::{self, Params};
+use pg_test_common::results::Results;
+use pg_test_common::scenario::{self, Cleanup, Values};
+use pg_test_common::settings::Settings;
+use pg_test_common::sweep::Sweep;
+use pg_test_common::work::{self, Work};
+use pg_test_common::{statements, stats, take_option};
+use postgres::Connection;
+use uuid::Uuid;
+
+static APP: &str = "insert-test";
+// static URL: &'static str = "postgresql://kelly@localhost:5432/test";
+const THREAD_COUNT: u32 = 16;
+const THREAD_INSERTS: u32 = 10000;
+const BATCH_SIZE: u32 = 100;
+const PIPELINE_DEPTH: u32 = 16;
+static INSERT_SQL: &str = "INSERT INTO person (otherid, name, data) VALUES ($1, $2, $3)";
+
+// What the threads of a phase run with
+struct Workload {
+    url: String,
+    thread_count: i32,
+    thread_inserts: i32,
+    batch_size: i32,
+    // Inserts each connection of the pipelined phase keeps in flight
+    pipeline_depth: usize,
+    name: String,
+    data: String,
+    settings: Settings,
+}
+
+// The tables a phase writes to
+static TABLES: [&str; 1] = ["person"];
+
+/*
+ * Runs a phase's threads with their sessions set to the durability, if any,
+ * returning the work they did and their latencies
+ */
+type RunThreads = fn(&Arc<Workload>, Option<Durability>) -> (Work, Histogram);
+
+// A phase's name, the mode its metrics are labelled with, its threads and its durability
+type Phase = (&'static str, &'static str, RunThreads, Option<Durability>);
+
+struct Person {
+    _id: i32,
+    otherid: Uuid,
+    name: String,
+    data: Option<String>,
+}
+
+impl Person {
+    // A new person with the workload's payload
+    fn new(workload: &Workload) -> Person {
+        Person {
+            _id: 0,
+            otherid: Uuid::new_v4(),
+            name: workload.name.clone(),
+            data: Some(workload.data.clone())
+        }
+    }
+
+    // The parameters of INSERT_SQL for the async engine
+    fn params(self) -> Params {
+        vec![
+            pipeline::param(uuid1::Uuid::from_bytes(*self.otherid.as_bytes())),
+            pipeline::param(self.name),
+            pipeline::param(self.data),
+        ]
+    }
+}
+
+/*
+ * Record the latency of each row a transaction committed as the time the
+ * transaction took divided by its rows, so that separate and batched
+ * transactions can be compared per row.
+ */
+fn record_rows(histogram: &mut Histogram, start: Instant, rows: i32) {
+    if rows <= 0 {
+        return;
+    }
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+    histogram.increment_by(nanos / rows as u64, rows as u64);
+    metrics::observe("Insert", nanos / rows as u64, rows as u64);
+}
+
+/*
+ * Insert rows in one transaction and count it in `work`. A transaction that
+ * fails is also counted as an error by the metrics and the thread moves on
+ * to the next one.
+ */
+fn insert_txn(conn: &Connection, workload: &Workload, work: &mut Work, histogram: &mut Histogram, rows: i32) {
+    let start = Instant::now();
+    let inserted = conn.transaction().and_then(|trans| {
+        for _number in 0..rows {
+            let p = Person::new(workload);
+
+            trans.execute(INSERT_SQL,
+                          &[&p.otherid, &p.name, &p.data])?;
+        }
+
+        trans.commit()
+    });
+
+    match inserted {
+        Ok(()) => {
+            record_rows(histogram, start, rows);
+            work.commit(rows.max(0) as u64);
+        }
+        Err(e) => {
+            metrics::error("Insert", &e);
+            work.fail();
+        }
+    }
+}
+
+// The work of every thread and their merged latencies
+fn join_threads(handles: Vec<thread::JoinHandle<(Work, Histogram)>>) -> (Work, Histogram) {
+    let mut work = Work::default();
+    let mut histogram = Histogram::new();
+    for handle in handles {
+        let (thread_work, thread_histogram) = handle.join().unwrap();
+        work.add(&thread_work);
+        histogram.merge(&thread_histogram);
+    }
+    (work, histogram)
+}
+
+fn run_separate_txn_threads(workload: &Arc<Workload>, durability: Option<Durability>) -> (Work, Histogram) {
+    let mut handles = Vec::new();
+    for _number in 0..workload.thread_count {
+        let workload = Arc::clone(workload);
+        let h = thread::spawn(move || {
+            separate_txns(&workload, durability)
+        });
+        handles.push(h);
+    };
+
+    join_threads(handles)
+}
+
+fn separate_txns(workload: &Workload, durability: Option<Durability>) -> (Work, Histogram) {
+    let thread_inserts = workload.thread_inserts;
+    let conn = connect_writer(workload, durability);
+    let mut work = Work::thread();
+    let mut histogram = Histogram::new();
+
+    for _number in 0..thread_inserts {
+        insert_txn(&conn, workload, &mut work, &mut histogram, 1);
+    }
+
+    (work, histogram)
+}
+
+fn connect_writer(workload: &Workload, durability: Option<Durability>) -> Connection {
+    let conn = pg_test_common::connect(&workload.url, APP);
+    workload.settings.apply(&conn);
+    if let Some(durability) = durability {
+        durability.apply(&conn);
+    }
+    conn
+}
+
+fn delete_table(conn: &Connection) {
+    let trans = conn.transaction().unwrap();
+
+    trans.execute("DELETE FROM person;", &[]).unwrap();
+
+    trans.commit().unwrap();
+}
+
+fn run_batched_txn_threads(workload: &Arc<Workload>, durability: Option<Durability>) -> (Work, Histogram) {
+    let mut handles = Vec::new();
+
+    for _number in 0..workload.thread_count {
+        let workload = Arc::clone(workload);
+        let h = thread::spawn(move || {
+           batched_txns(&workload, durability)
+        });
+        handles.push(h);
+    };
+
+    join_threads(handles)
+}
+
+fn batched_txns(workload: &Workload, durability: Option<Durability>) -> (Work, Histogram) {
+    let thread_inserts = workload.thread_inserts;
+    let batch_size = workload.batch_size;
+    let conn = connect_writer(workload, durability);
+    let mut work = Work::thread();
+    let mut histogram = Histogram::new();
+
+    let txn_count = thread_inserts / batch_size;
+
+    for _txn_num in 0..txn_count {
+        insert_txn(&conn, workload, &mut work, &mut histogram, batch_size);
+    }
+
+    // Perform any extra inserts if THREAD_INSERTS isn't evenly factored by
+    // BATCH_SIZE
+    let insert_remainder = thread_inserts % batch_size;
+    if insert_remainder > 0 {
+        insert_txn(&conn, workload, &mut work, &mut histogram, insert_remainder);
+    }
+
+    (work, histogram)
+}
+
+/*
+ * Insert single rows, each in a transaction of its own like the separate
+ * phase, but through the async engine with up to the pipeline depth of them
+ * in flight on every connection. A connection stands in for a thread, so the
+ * phase inserts the same rows over as many connections as the other phases
+ * use threads, all driven from this thread.
+ */
+fn run_pipelined_threads(workload: &Arc<Workload>, durability: Option<Durability>) -> (Work, Histogram) {
+    let runtime = pipeline::runtime();
+    let clients = pipeline::connect(&runtime, &workload.url, APP, workload.thread_count as u32);
+    for client in &clients {
+        workload.settings.apply_async(&runtime, client);
+    }
+    if let Some(durability) = durability {
+        durability.apply_async(&runtime, &clients);
+    }
+    let statements: Vec<_> = clients
+        .iter()
+        .map(|client| runtime.block_on(client.prepare(INSERT_SQL)).unwrap())
+        .collect();
+
+    let connections = clients.iter().zip(&statements).map(|(client, statement)| {
+        stream::iter(0..workload.thread_inserts)
+            .map(move |_| {
+                let params = Person::new(workload).params();
+                pipeline::timed("Insert", client.execute_raw(statement, params))
+            })
+            .buffer_unordered(workload.pipeline_depth)
+            .fold((Work::thread(), Histogram::new()), |(mut work, mut histogram), inserted| {
+                pipeline::record(&mut work, &mut histogram, 1, inserted);
+                future::ready((work, histogram))
+            })
+    });
+
+    let mut work = Work::default();
+    let mut histogram = Histogram::new();
+    for (connection_work, connection_histogram) in runtime.block_on(future::join_all(connections)) {
+        work.add(&connection_work);
+        histogram.merge(&connection_histogram);
+    }
+    (work, histogram)
+}
+
+/*
+ * Capture the plan of the person insert before a phase. The insert is rolled
+ * back so it doesn't count towards the phase.
+ */
+fn explain_insert(conn: &Connection, dir: &str, phase: &str, workload: &Workload) {
+    let mut explainer = Explainer::new(dir, APP, phase);
+    let p = Person::new(workload);
+
+    explainer.explain(conn, "person insert", INSERT_SQL,
+                      &[&p.otherid, &p.name, &p.data], &[]);
+    explainer.finish();
+}
+
+fn usage() {
+    println!("Usage: insert-test PG_URL [THREAD_COUNT] [THREAD_INSERTS] [BATCH_SIZE] [--pipeline DEPTH] [--durability LIST] [--scenario FILE] [--explain DIR] [--save FILE] [--compare FILE] [--threshold PCT] [--trials N] [--order fixed|alternate|random] [--table FILE] [--format text|json] [--precision DIGITS] [--max-latency MS] [--histogram-log DIR] [--log-interval MS] [--metrics-port PORT] [--metrics-textfile FILE] [--set NAME=VALUE]...");
+    println!();
+    println!("The pipelined phase inserts single rows through the async engine, keeping up to");
+    println!("DEPTH (Default: 16) of them in flight on each of THREAD_COUNT connections.");
+    println!();
+    println!("--durability runs every phase once for each of a comma separated LIST of");
+    println!("synchronous_commit settings (on, off, local, remote_write) and unlogged, which");
+    println!("makes person an unlogged table, and compares their throughput and latency");
+    println!("with those of the first one given.");
+    println!();
+    println!("--scenario runs the phases, counts, payload and cleanup described by a TOML");
+    println!("FILE. Arguments given on the command line override it; a positional argument");
+    println!("given as - keeps the scenario's setting.");
+    println!();
+    println!("THREAD_COUNT and BATCH_SIZE may be lists of values and ranges such as 1..64*2");
+    println!("or 1,10..100+10 to run every combination of them, and --table writes the");
+    println!("throughput and latency percentiles of each combination to FILE as CSV.");
+    println!();
+    println!("Latencies are recorded to DIGITS significant figures (Default: 3) up to MS");
+    println!("milliseconds (Default: 60000); larger ones are counted as overflows. With");
+    println!("--histogram-log the latencies of every MS interval (Default: 1000) of each phase");
+    println!("are written to DIR as an HdrHistogram interval log.");
+    println!();
+    println!("--metrics-port serves live Prometheus metrics on localhost:PORT, and");
+    println!("--metrics-textfile keeps them in FILE for node_exporter's textfile collector.");
+    println!();
+    println!("--set sets NAME to VALUE on every connection the workers open, and may be");
+    println!("repeated. The settings are recorded with the results.");
+}
+
+// A positional count, or the list of counts to sweep over
+fn sweep_arg(args: &[String], index: usize, name: &str, scenario: Option<&Values>, default: u32) -> Vec<u32> {
+    cli::resolve_counts(scenario::positional(args, index), scenario, default).unwrap_or_else(|e| {
+        eprintln!("{}: {}", name, e);
+        usage();
+        process::exit(1)
+    })
+}
+
+fn run_phase(conn: &Connection,
+             (phase, mode, run_threads, durability): Phase,
+             workload: &Arc<Workload>,
+             options: &Options,
+             results: &mut Results,
+             comparison: &mut Comparison) {
+    let base_label = results.label(phase);
+    let phase = &durability.map_or(phase.to_string(), |durability| durability.label(phase));
+    if let Some(durability) = durability {
+        durability.setup(conn, &TABLES);
+    }
+    if let Some(ref dir) = options.explain_dir {
+        explain_insert(conn, dir, phase, workload);
+    }
+    let statements_tracked = statements::reset(conn, &[]);
+    let stats_before = stats::Snapshot::take(conn);
+    let rows_before = work::count_rows(conn, "person");
+    metrics::start_phase(mode, &results.label(phase));
+    let start = Instant::now();
+    let (work, histogram) = run_threads(workload, durability);
+    let end = Instant::now();
+    report!("{}: {:?}", phase, end.duration_since(start));
+    work.print(phase);
+    work::verify_rows(phase, "person", rows_before, work::count_rows(conn, "person"), &work);
+    results.add_phase(phase, work.rows, end.duration_since(start), &[("Insert", &histogram)]);
+    if let Some(durability) = durability {
+        comparison.add(&base_label, durability, work.rows, end.duration_since(start), &histogram);
+    }
+    stats::print_delta(phase, &stats_before, &stats::Snapshot::take_settled(conn));
+    if statements_tracked {
+        statements::print_top(conn, phase, &[]);
+    }
+
+    if options.scenario.cleanup == Cleanup::Phase {
+        delete_table(conn);
+    }
+    if let Some(durability) = durability {
+        durability.teardown(conn, &TABLES);
+    }
+}
+
+/*
+ * What to run: the counts to sweep over, the inserts each thread makes, how
+ * many of them the pipelined phase keeps in flight, the durabilities to run
+ * every phase with (none leaves the server's setting alone) and the options
+ * shared with the other utilities.
+ */
+pub struct Config {
+    pub url: String,
+    pub threads: Vec<u32>,
+    pub inserts: u32,
+    pub batch_sizes: Vec<u32>,
+    pub pipeline_depth: u32,
+    pub durability: Vec<Durability>,
+    pub options: Options,
+}
+
+impl Config {
+    // Parse insert-test's positional command line
+    pub fn from_args(mut args: Vec<String>) -> Config {
+        let options = Options::from_args(&mut args, APP);
+        let scenario = &options.scenario;
+        let pipeline_depth = take_option(&mut args, "--pipeline").map_or(PIPELINE_DEPTH, |depth| {
+            cli::parse_count(&depth).unwrap_or_else(|e| {
+                eprintln!("--pipeline: {}", e);
+                process::exit(1)
+            })
+        });
+
+        let durability = durability::from_args(&mut args, scenario);
+
+        let url = match scenario::positional(&args, 1) {
+            Some(url) => url.to_string(),
+            None => scenario.connection.url.clone().unwrap_or_else(|| {
+                usage();
+                process::exit(1)
+            }),
+        };
+        let threads = sweep_arg(&args, 2, "THREAD_COUNT", scenario.threads.as_ref(), THREAD_COUNT);
+        let inserts = match scenario::positional(&args, 3) {
+            Some(arg) => cli::parse_count(arg).unwrap_or_else(|e| {
+                eprintln!("THREAD_INSERTS: {}", e);
+                usage();
+                process::exit(1)
+            }),
+            None => scenario.iterations.unwrap_or(THREAD_INSERTS),
+        };
+        let batch_sizes = sweep_arg(&args, 4, "BATCH_SIZE", scenario.batch_size.as_ref(), BATCH_SIZE);
+
+        Config { url, threads, inserts, batch_sizes, pipeline_depth, durability, options }
+    }
+
+    // The matches of subcommand()
+    pub fn from_matches(matches: &ArgMatches) -> Config {
+        let options = Options::from_matches(matches, APP);
+        let scenario = &options.scenario;
+
+        Config {
+            url: cli::url(matches, scenario),
+            threads: cli::sweep_counts(matches, "threads", scenario.threads.as_ref(), THREAD_COUNT),
+            inserts: cli::parsed(matches, "iterations")
+                .or(scenario.iterations)
+                .unwrap_or(THREAD_INSERTS),
+            batch_sizes: cli::sweep_counts(matches, "batchSize", scenario.batch_size.as_ref(), BATCH_SIZE),
+            pipeline_depth: cli::parsed(matches, "pipeline").unwrap_or(PIPELINE_DEPTH),
+            durability: durability::from_matches(matches, scenario),
+            options,
+        }
+    }
+}
+
+pub fn subcommand<'a, 'b>(name: &str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about("Compare inserting rows in separate transactions with inserting them in batches")
+        .args(&cli::args("Inserts per thread (Default: 10000)"))
+        .arg(Arg::with_name("batchSize")
+             .help("Rows inserted per batched transaction, or a list of sizes to sweep (Default: 100)")
+             .short("b")
+             .long("batch-size")
+             .value_name("COUNT")
+             .takes_value(true)
+             .validator(cli::counts))
+        .arg(Arg::with_name("pipeline")
+             .help("Inserts each connection of the pipelined phase keeps in flight (Default: 16)")
+             .long("pipeline")
+             .value_name("DEPTH")
+             .takes_value(true)
+             .validator(cli::count))
+        .arg(durability::arg())
+}
+
+pub fn run(config: Config) {
+    let options = &config.options;
+    let scenario = &options.scenario;
+    let mut sweep = Sweep::new();
+    sweep.add("threads", config.threads.clone());
+    sweep.add("batch_size", config.batch_sizes.clone());
+
+    // Each phase's name, the mode its metrics are labelled with and its threads
+    let phases: [(&str, &str, RunThreads); 3] = [
+        ("Separate txns", "separate", run_separate_txn_threads),
+        ("Batched txns", "batched", run_batched_txn_threads),
+        ("Pipelined inserts", "pipelined", run_pipelined_threads),
+    ];
+    let phases = scenario.select_phases(&phases, |phase| phase.1);
+
+    // Every phase runs once per durability, or as the server is set up without any
+    let durabilities: Vec<Option<Durability>> = if config.durability.is_empty() {
+        vec![None]
+    } else {
+        config.durability.iter().cloned().map(Some).collect()
+    };
+    let phases: Vec<Phase> = phases
+        .iter()
+        .flat_map(|&(phase, mode, run_threads)| {
+            durabilities.iter().map(move |&durability| (phase, mode, run_threads, durability))
+        })
+        .collect();
+
+    let conn = pg_test_common::connect(&config.url, APP);
+    options.settings.check(&conn);
+    let mut results = Results::new(APP, &options.settings);
+    let mut comparison = Comparison::new(&config.durability);
+
+    let points = sweep.points();
+    for (p, point) in points.iter().enumerate() {
+        /*
+         * Only the batched phase depends on the batch size, so the others run
+         * at the first size of each sweep and are not repeated for the rest.
+         */
+        let point_phases: Vec<Phase> = if point.get("batch_size") == config.batch_sizes[0] {
+            phases.clone()
+        } else {
+            phases.iter().cloned().filter(|phase| phase.1 == "batched").collect()
+        };
+        if point_phases.is_empty() {
+            continue;
+        }
+
+        if sweep.is_sweep() {
+            report!("Sweep point {} of {}: {}", p + 1, points.len(), point.describe());
+            results.set_point(point);
+        }
+        let workload = Arc::new(Workload {
+            url: config.url.clone(),
+            thread_count: point.get("threads") as i32,
+            thread_inserts: config.inserts as i32,
+            batch_size: point.get("batch_size") as i32,
+            pipeline_depth: config.pipeline_depth as usize,
+            name: scenario.payload.name.clone(),
+            data: scenario.payload.data(),
+            settings: options.settings.clone(),
+        });
+
+        for trial in 0..options.trials {
+            let trial_phases = options.order.arrange(&point_phases, trial);
+            if options.trials > 1 {
+                let names: Vec<String> = trial_phases
+                    .iter()
+                    .map(|&(phase, _, _, durability)| {
+                        durability.map_or(phase.to_string(), |durability| durability.label(phase))
+                    })
+                    .collect();
+                report!("Trial {} of {}: {}", trial + 1, options.trials, names.join(", "));
+            }
+
+            for (i, &phase) in trial_phases.iter().enumerate() {
+                if p > 0 || trial > 0 || i > 0 {
+                    thread::sleep(Duration::from_secs(1));
+                }
+
+                run_phase(&conn, phase, &workload, options, &mut results, &mut comparison);
+            }
+        }
+    }
+
+    if options.scenario.cleanup == Cleanup::End {
+        delete_table(&conn);
+    }
+    comparison.print();
+    options.output.finish(&results);
+    metrics::finish();
+}
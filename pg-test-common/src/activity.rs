@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use connect;
+
+/*
+ * Counts of what the benchmark's backends were waiting on, keyed by
+ * "wait_event_type:wait_event". Active backends that aren't waiting on
+ * anything are counted as "CPU".
+ */
+pub struct WaitEvents {
+    pub samples: u64,
+    pub backend_samples: u64,
+    pub events: BTreeMap<String, u64>,
+}
+
+impl WaitEvents {
+    pub fn print(&self, phase: &str) {
+        report!(
+            "{} wait events ({} samples, {} backend samples):",
+            phase, self.samples, self.backend_samples
+        );
+
+        let mut events: Vec<(&String, &u64)> = self.events.iter().collect();
+        events.sort_by(|a, b| b.1.cmp(a.1));
+        for (event, count) in events {
+            report!(
+                "  {}: {} ({:.1}%)",
+                event,
+                count,
+                *count as f64 * 100.0 / self.backend_samples as f64
+            );
+        }
+    }
+}
+
+/*
+ * Poll pg_stat_activity for the backends tagged with `application_name`
+ * every `interval` until `stop` is set. The sampler's own backend uses a
+ * different application_name so it never counts itself.
+ */
+pub fn spawn(
+    url: String,
+    application_name: String,
+    interval: Duration,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<WaitEvents> {
+    thread::spawn(move || {
+        let conn = connect(&url, &[&application_name, "-sampler"].concat());
+        let mut wait_events = WaitEvents {
+            samples: 0,
+            backend_samples: 0,
+            events: BTreeMap::new(),
+        };
+
+        while !stop.load(Ordering::SeqCst) {
+            let rows = conn
+                .query(
+                    "SELECT coalesce(wait_event_type || ':' || wait_event, \
+                     CASE WHEN state = 'active' THEN 'CPU' ELSE state END) \
+                     FROM pg_stat_activity WHERE application_name = $1",
+                    &[&application_name],
+                )
+                .unwrap();
+
+            wait_events.samples += 1;
+            for row in rows.iter() {
+                let event: Option<String> = row.get(0);
+                let event = event.unwrap_or_else(|| String::from("unknown"));
+                *wait_events.events.entry(event).or_insert(0) += 1;
+                wait_events.backend_samples += 1;
+            }
+
+            thread::sleep(interval);
+        }
+
+        wait_events
+    })
+}
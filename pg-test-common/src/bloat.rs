@@ -0,0 +1,203 @@
+use postgres::Connection;
+
+struct IndexSize {
+    name: String,
+    size: i64,
+}
+
+struct TableStats {
+    name: String,
+    relation_size: i64,
+    total_relation_size: i64,
+    writes: i64,
+    updates: i64,
+    hot_updates: i64,
+    live_tuples: i64,
+    dead_tuples: i64,
+    indexes: Vec<IndexSize>,
+}
+
+/*
+ * The size and update counters of a set of tables and their indexes. The
+ * tuple counts come from the cumulative statistics system, so the snapshot
+ * taken at the end of a phase should be taken once the workers' statistics
+ * have arrived (see stats::Snapshot::take_settled).
+ */
+pub struct Snapshot {
+    tables: Vec<TableStats>,
+}
+
+impl Snapshot {
+    pub fn take(conn: &Connection, tables: &[&str]) -> Snapshot {
+        conn.execute("SELECT pg_stat_clear_snapshot()", &[]).unwrap();
+
+        let tables = tables
+            .iter()
+            .map(|&table| {
+                let rows = conn
+                    .query(
+                        "SELECT pg_relation_size(relid), pg_total_relation_size(relid), \
+                         n_tup_ins + n_tup_upd + n_tup_del, n_tup_upd, n_tup_hot_upd, \
+                         n_live_tup, n_dead_tup \
+                         FROM pg_stat_user_tables WHERE relid = $1::text::regclass",
+                        &[&table],
+                    )
+                    .unwrap();
+                let row = rows.get(0);
+
+                let indexes = conn
+                    .query(
+                        "SELECT indexrelid::regclass::text, pg_relation_size(indexrelid) \
+                         FROM pg_index WHERE indrelid = $1::text::regclass \
+                         ORDER BY indexrelid::regclass::text",
+                        &[&table],
+                    )
+                    .unwrap()
+                    .iter()
+                    .map(|index| IndexSize {
+                        name: index.get(0),
+                        size: index.get(1),
+                    })
+                    .collect();
+
+                TableStats {
+                    name: table.to_string(),
+                    relation_size: row.get(0),
+                    total_relation_size: row.get(1),
+                    writes: row.get(2),
+                    updates: row.get(3),
+                    hot_updates: row.get(4),
+                    live_tuples: row.get(5),
+                    dead_tuples: row.get(6),
+                    indexes,
+                }
+            })
+            .collect();
+
+        Snapshot { tables }
+    }
+}
+
+fn pgstattuple_installed(conn: &Connection) -> bool {
+    let rows = conn
+        .query(
+            "SELECT count(*) FROM pg_extension WHERE extname = 'pgstattuple'",
+            &[],
+        )
+        .unwrap();
+    let installed: i64 = rows.get(0).get(0);
+    installed > 0
+}
+
+/*
+ * Report the size, dead tuples and HOT update ratio of every table that was
+ * written to between the two snapshots, along with the size of its indexes.
+ * When the pgstattuple extension is installed the tables and btree indexes
+ * are also scanned for free space and leaf density, which is the most
+ * direct measure of how bloated they are.
+ */
+pub fn print_report(conn: &Connection, phase: &str, before: &Snapshot, after: &Snapshot) {
+    let pgstattuple = pgstattuple_installed(conn);
+
+    report!("{} table bloat:", phase);
+    for (b, a) in before.tables.iter().zip(&after.tables) {
+        if a.writes == b.writes {
+            continue;
+        }
+
+        let updates = a.updates - b.updates;
+        let hot_updates = a.hot_updates - b.hot_updates;
+        report!(
+            "  {}: size: {} bytes ({:+}) total: {} bytes ({:+}) live tuples: {} dead tuples: {} \
+             updates: {} HOT: {} ({:.1}%)",
+            a.name,
+            a.relation_size,
+            a.relation_size - b.relation_size,
+            a.total_relation_size,
+            a.total_relation_size - b.total_relation_size,
+            a.live_tuples,
+            a.dead_tuples,
+            updates,
+            hot_updates,
+            if updates == 0 {
+                0.0
+            } else {
+                hot_updates as f64 * 100.0 / updates as f64
+            }
+        );
+        if pgstattuple {
+            print_table_tuples(conn, &a.name);
+        }
+
+        for index in &a.indexes {
+            let size_before = b
+                .indexes
+                .iter()
+                .find(|i| i.name == index.name)
+                .map(|i| i.size)
+                .unwrap_or(0);
+            report!(
+                "    index {}: size: {} bytes ({:+})",
+                index.name,
+                index.size,
+                index.size - size_before
+            );
+            if pgstattuple {
+                print_index_density(conn, &index.name);
+            }
+        }
+    }
+}
+
+// Bloat is the share of the table that is free space or dead tuples
+fn print_table_tuples(conn: &Connection, table: &str) {
+    let rows = conn
+        .query(
+            "SELECT free_space, free_percent, dead_tuple_percent \
+             FROM pgstattuple($1::text::regclass)",
+            &[&table],
+        )
+        .unwrap();
+    let row = rows.get(0);
+    let free_space: i64 = row.get(0);
+    let free_percent: f64 = row.get(1);
+    let dead_percent: f64 = row.get(2);
+
+    report!(
+        "    pgstattuple: free space: {} bytes ({:.1}%) dead tuples: {:.1}% bloat: {:.1}%",
+        free_space,
+        free_percent,
+        dead_percent,
+        free_percent + dead_percent
+    );
+}
+
+// pgstatindex only understands btree indexes
+fn print_index_density(conn: &Connection, index: &str) {
+    let rows = conn
+        .query(
+            "SELECT s.avg_leaf_density, s.leaf_fragmentation \
+             FROM pg_class c JOIN pg_am am ON am.oid = c.relam, \
+             LATERAL pgstatindex(c.oid) s \
+             WHERE c.oid = $1::text::regclass AND am.amname = 'btree'",
+            &[&index],
+        )
+        .unwrap();
+    if rows.is_empty() {
+        return;
+    }
+    let row = rows.get(0);
+    let density: f64 = row.get(0);
+    let fragmentation: f64 = row.get(1);
+
+    // An index without any leaf pages reports NaN
+    if density.is_nan() {
+        return;
+    }
+    report!(
+        "      pgstatindex: avg leaf density: {:.1}% leaf fragmentation: {:.1}% bloat: {:.1}%",
+        density,
+        fragmentation,
+        100.0 - density
+    );
+}
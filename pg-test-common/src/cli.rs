@@ -0,0 +1,301 @@
+use std::fmt::Display;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{Arg, ArgMatches, Error, ErrorKind};
+
+use histogram;
+use metrics;
+use order::Order;
+use results::Output;
+use scenario::{Scenario, Values};
+use settings::{self, Settings};
+use sweep;
+use take_option;
+use take_parsed;
+
+/*
+ * The options every utility shares: the scenario it runs, where its results
+ * go, how many times and in what order its phases run and the session
+ * settings of its workers. Taking them also configures how latencies are
+ * recorded and exposed.
+ */
+pub struct Options {
+    pub explain_dir: Option<String>,
+    pub output: Output,
+    pub trials: u32,
+    pub order: Order,
+    pub settings: Settings,
+    pub scenario: Scenario,
+}
+
+impl Options {
+    // Take the shared --name VALUE options off a positional command line
+    pub fn from_args(args: &mut Vec<String>, app: &str) -> Options {
+        let scenario = Scenario::from_args(args);
+        let explain_dir = take_option(args, "--explain");
+        let output = Output::from_args(args);
+        histogram::configure_from_args(args);
+        metrics::configure_from_args(args, app);
+        let trials = take_parsed(args, "--trials").or(scenario.trials).unwrap_or(1);
+        let order = take_parsed(args, "--order")
+            .or_else(|| scenario.order())
+            .unwrap_or(Order::Fixed);
+        let settings = Settings::from_args(args, &scenario);
+
+        Options::new(explain_dir, output, trials, order, settings, scenario)
+    }
+
+    // The shared options of a subcommand, as defined by args()
+    pub fn from_matches(matches: &ArgMatches, app: &str) -> Options {
+        let scenario = match matches.value_of("scenario") {
+            Some(path) => Scenario::load(path),
+            None => Scenario::default(),
+        };
+        histogram::configure(
+            parsed(matches, "precision"),
+            parsed(matches, "maxLatency").map(Duration::from_millis),
+            string(matches, "histogramLog"),
+            parsed(matches, "logInterval").map(Duration::from_millis),
+        );
+        metrics::configure(app, parsed(matches, "metricsPort"), string(matches, "metricsTextfile"));
+        let output = Output::new(
+            string(matches, "save"),
+            string(matches, "compare"),
+            parsed(matches, "threshold"),
+            string(matches, "table"),
+            parsed(matches, "format"),
+        );
+        let trials = parsed(matches, "trials").or(scenario.trials).unwrap_or(1);
+        let order = parsed(matches, "order")
+            .or_else(|| scenario.order())
+            .unwrap_or(Order::Fixed);
+        let settings = Settings::from_matches(matches, &scenario);
+
+        Options::new(string(matches, "explain"), output, trials, order, settings, scenario)
+    }
+
+    fn new(
+        explain_dir: Option<String>,
+        output: Output,
+        trials: u32,
+        order: Order,
+        settings: Settings,
+        scenario: Scenario,
+    ) -> Options {
+        // A scenario can still ask for no trials at all
+        if trials == 0 {
+            eprintln!("--trials must be at least 1");
+            process::exit(1);
+        }
+
+        Options {
+            explain_dir,
+            output,
+            trials,
+            order,
+            settings,
+            scenario,
+        }
+    }
+}
+
+/*
+ * The options every subcommand takes. `iterations` is the help of
+ * --iterations, as what is repeated and how often differs between them.
+ */
+pub fn args<'a, 'b>(iterations: &'a str) -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("url")
+            .help("Postgres URL, required unless the scenario gives one")
+            .short("u")
+            .long("url")
+            .value_name("URL")
+            .takes_value(true),
+        Arg::with_name("threads")
+            .help("Thread count, or a list of counts to sweep (Default: 16)")
+            .short("t")
+            .long("threads")
+            // hierarchy-test's original name for it
+            .alias("thread-count")
+            .value_name("COUNT")
+            .takes_value(true)
+            .validator(counts),
+        Arg::with_name("iterations")
+            .help(iterations)
+            .short("i")
+            .long("iterations")
+            .value_name("COUNT")
+            .takes_value(true)
+            .validator(count),
+        Arg::with_name("scenario")
+            .help("Run the scenario described by this TOML file; options given here override it")
+            .long("scenario")
+            .value_name("FILE")
+            .takes_value(true),
+        Arg::with_name("explain")
+            .help("Write EXPLAIN ANALYZE plans of each phase's statements to this directory")
+            .long("explain")
+            .value_name("DIR")
+            .takes_value(true),
+        Arg::with_name("save")
+            .help("Save the throughput and latency percentiles of each phase to this file")
+            .long("save")
+            .value_name("FILE")
+            .takes_value(true),
+        Arg::with_name("compare")
+            .help("Compare the results against a file saved by an earlier run")
+            .long("compare")
+            .value_name("FILE")
+            .takes_value(true),
+        Arg::with_name("threshold")
+            .help("Percent change flagged as a regression by --compare (Default: 10)")
+            .long("threshold")
+            .value_name("PCT")
+            .takes_value(true)
+            .validator(parses::<f64>),
+        Arg::with_name("table")
+            .help("Write the results of every point of a sweep to this file as CSV")
+            .long("table")
+            .value_name("FILE")
+            .takes_value(true),
+        Arg::with_name("format")
+            .help("Format of the results printed at the end of the run (Default: text)")
+            .long("format")
+            .value_name("FORMAT")
+            .takes_value(true)
+            .possible_values(&["text", "json"]),
+        Arg::with_name("trials")
+            .help("Run every phase this many times and summarize the results (Default: 1)")
+            .long("trials")
+            .value_name("N")
+            .takes_value(true)
+            .validator(count),
+        Arg::with_name("order")
+            .help("Order of the phases on each trial (Default: fixed)")
+            .long("order")
+            .value_name("ORDER")
+            .takes_value(true)
+            .possible_values(&["fixed", "alternate", "random"]),
+        Arg::with_name("set")
+            .help("Set this setting on every connection the workers open; may be repeated")
+            .long("set")
+            .value_name("NAME=VALUE")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(settings::validate),
+        Arg::with_name("precision")
+            .help("Significant figures of recorded latencies, 1 to 5 (Default: 3)")
+            .long("precision")
+            .value_name("DIGITS")
+            .takes_value(true)
+            .validator(|n| match n.parse::<usize>() {
+                Ok(n) if (1..=5).contains(&n) => Ok(()),
+                _ => Err(String::from("must be between 1 and 5")),
+            }),
+        Arg::with_name("maxLatency")
+            .help("Largest latency recorded; larger ones are counted as overflows (Default: 60000)")
+            .long("max-latency")
+            .value_name("MS")
+            .takes_value(true)
+            .validator(parses::<u64>),
+        Arg::with_name("histogramLog")
+            .help("Write an HdrHistogram interval log of each trial to this directory")
+            .long("histogram-log")
+            .value_name("DIR")
+            .takes_value(true),
+        Arg::with_name("logInterval")
+            .help("Interval of the histogram log (Default: 1000)")
+            .long("log-interval")
+            .value_name("MS")
+            .takes_value(true)
+            .validator(parses::<u64>),
+        Arg::with_name("metricsPort")
+            .help("Serve Prometheus metrics on this port of localhost while running")
+            .long("metrics-port")
+            .value_name("PORT")
+            .takes_value(true)
+            .validator(parses::<u16>),
+        Arg::with_name("metricsTextfile")
+            .help("Keep Prometheus metrics in this file for node_exporter's textfile collector")
+            .long("metrics-textfile")
+            .value_name("FILE")
+            .takes_value(true),
+    ]
+}
+
+// Validates an option whose value must parse as a T
+pub fn parses<T>(value: String) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.parse::<T>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+// Validates a count, which must be at least 1
+pub fn count(value: String) -> Result<(), String> {
+    parse_count(&value).map(|_| ())
+}
+
+// Validates a count or a list of counts to sweep over
+pub fn counts(value: String) -> Result<(), String> {
+    resolve_counts(Some(&value), None, 1).map(|_| ())
+}
+
+pub fn parse_count(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(0) => Err(String::from("must be at least 1")),
+        Ok(count) => Ok(count),
+        Err(_) => Err(format!("invalid count: {}", value)),
+    }
+}
+
+/*
+ * The counts to run with: those given on the command line, else the
+ * scenario's, else the default. Counts can be swept over, but every one of
+ * them must be at least 1.
+ */
+pub fn resolve_counts(arg: Option<&str>, scenario: Option<&Values>, default: u32) -> Result<Vec<u32>, String> {
+    let counts = match (arg, scenario) {
+        (Some(arg), _) => sweep::parse_values(arg)?,
+        (None, Some(values)) => values.parse()?,
+        (None, None) => vec![default],
+    };
+
+    if counts.contains(&0) {
+        return Err(String::from("counts must be at least 1"));
+    }
+    Ok(counts)
+}
+
+// The value of an option that was validated to parse as a T
+pub fn parsed<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    matches.value_of(name).and_then(|value| value.parse().ok())
+}
+
+pub fn string(matches: &ArgMatches, name: &str) -> Option<String> {
+    matches.value_of(name).map(String::from)
+}
+
+pub fn url(matches: &ArgMatches, scenario: &Scenario) -> String {
+    string(matches, "url")
+        .or_else(|| scenario.connection.url.clone())
+        .unwrap_or_else(|| {
+            Error::with_description(
+                "a Postgres URL is required, either with --url or in the scenario",
+                ErrorKind::MissingRequiredArgument,
+            )
+            .exit()
+        })
+}
+
+// The counts of a sweepable option, defaulting to the scenario's
+pub fn sweep_counts(matches: &ArgMatches, name: &str, scenario: Option<&Values>, default: u32) -> Vec<u32> {
+    resolve_counts(matches.value_of(name), scenario, default).unwrap_or_else(|e| {
+        Error::with_description(&format!("invalid scenario {}: {}", name, e), ErrorKind::InvalidValue)
+            .exit()
+    })
+}
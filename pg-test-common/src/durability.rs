@@ -0,0 +1,280 @@
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{Arg, ArgMatches};
+use postgres::Connection;
+use tokio::runtime::Runtime;
+use tokio_postgres::Client;
+
+use histogram::Histogram;
+use scenario::Scenario;
+use take_option;
+
+/*
+ * How durably a phase commits its writes. Each synchronous_commit level is
+ * set on every connection that writes, and with unlogged tables the phase's
+ * tables skip the WAL entirely while commits still wait for it to flush as
+ * with synchronous_commit=on. Without a synchronous standby remote_write and
+ * on wait for the same local flush.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Durability {
+    On,
+    Off,
+    Local,
+    RemoteWrite,
+    Unlogged,
+}
+
+impl FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Durability, String> {
+        match s {
+            "on" => Ok(Durability::On),
+            "off" => Ok(Durability::Off),
+            "local" => Ok(Durability::Local),
+            "remote_write" => Ok(Durability::RemoteWrite),
+            "unlogged" => Ok(Durability::Unlogged),
+            _ => Err(format!(
+                "unknown durability {}, expected one of: on, off, local, remote_write, unlogged",
+                s
+            )),
+        }
+    }
+}
+
+// A comma separated list of durabilities
+pub fn parse_list(list: &str) -> Result<Vec<Durability>, String> {
+    list.split(',').map(str::parse).collect()
+}
+
+// The --durability option of the utilities that compare durabilities
+pub fn arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("durability")
+        .help(
+            "Run every phase with each of these comma separated durabilities: on, off, local, \
+               remote_write or unlogged (Default: the server's synchronous_commit)",
+        )
+        .long("durability")
+        .value_name("LIST")
+        .takes_value(true)
+        .validator(|list| parse_list(&list).map(|_| ()))
+}
+
+// The durabilities given by arg() or the scenario, empty when neither gives any
+pub fn from_matches(matches: &ArgMatches, scenario: &Scenario) -> Vec<Durability> {
+    match matches.value_of("durability") {
+        Some(list) => parse_list(list).unwrap(),
+        None => scenario.durability().unwrap_or_default(),
+    }
+}
+
+// Like from_matches(), taking --durability LIST off a positional command line
+pub fn from_args(args: &mut Vec<String>, scenario: &Scenario) -> Vec<Durability> {
+    match take_option(args, "--durability") {
+        Some(list) => parse_list(&list).unwrap_or_else(|e| {
+            eprintln!("--durability: {}", e);
+            process::exit(1)
+        }),
+        None => scenario.durability().unwrap_or_default(),
+    }
+}
+
+impl Durability {
+    pub fn name(self) -> &'static str {
+        match self {
+            Durability::On => "synchronous_commit=on",
+            Durability::Off => "synchronous_commit=off",
+            Durability::Local => "synchronous_commit=local",
+            Durability::RemoteWrite => "synchronous_commit=remote_write",
+            Durability::Unlogged => "unlogged tables",
+        }
+    }
+
+    fn synchronous_commit(self) -> &'static str {
+        match self {
+            Durability::On | Durability::Unlogged => "on",
+            Durability::Off => "off",
+            Durability::Local => "local",
+            Durability::RemoteWrite => "remote_write",
+        }
+    }
+
+    // How a phase run with this durability is named
+    pub fn label(self, phase: &str) -> String {
+        format!("{} ({})", phase, self.name())
+    }
+
+    // Set the synchronous_commit of a writer's session
+    pub fn apply(self, conn: &Connection) {
+        conn.execute(
+            "SELECT set_config('synchronous_commit', $1, false)",
+            &[&self.synchronous_commit()],
+        )
+        .unwrap();
+    }
+
+    // Like apply(), for the connections of the async engine
+    pub fn apply_async(self, runtime: &Runtime, clients: &[Client]) {
+        for client in clients {
+            runtime
+                .block_on(client.execute(
+                    "SELECT set_config('synchronous_commit', $1, false)",
+                    &[&self.synchronous_commit()],
+                ))
+                .unwrap();
+        }
+    }
+
+    /*
+     * Make the phase's tables unlogged before it starts, and logged again
+     * once it is done. Both rewrite the tables, so teardown() is best done
+     * after the phase's rows are deleted.
+     */
+    pub fn setup(self, conn: &Connection, tables: &[&str]) {
+        if self == Durability::Unlogged {
+            set_persistence(conn, tables, "UNLOGGED");
+        }
+    }
+
+    pub fn teardown(self, conn: &Connection, tables: &[&str]) {
+        if self == Durability::Unlogged {
+            set_persistence(conn, tables, "LOGGED");
+        }
+    }
+}
+
+fn set_persistence(conn: &Connection, tables: &[&str], persistence: &str) {
+    for table in tables {
+        conn.batch_execute(&["ALTER TABLE ", table, " SET ", persistence].concat())
+            .unwrap();
+    }
+}
+
+struct Entry {
+    phase: String,
+    durability: Durability,
+    operations: u64,
+    secs: f64,
+    histogram: Histogram,
+}
+
+/*
+ * The throughput and commit latency of every phase at each durability, over
+ * all of its trials, so that each can be compared with the phase at the
+ * first durability it was run with.
+ */
+pub struct Comparison {
+    durabilities: Vec<Durability>,
+    entries: Vec<Entry>,
+}
+
+impl Comparison {
+    pub fn new(durabilities: &[Durability]) -> Comparison {
+        Comparison {
+            durabilities: durabilities.to_vec(),
+            entries: Vec::new(),
+        }
+    }
+
+    // Record a trial of `phase`, labelled with its sweep point but not its durability
+    pub fn add(
+        &mut self,
+        phase: &str,
+        durability: Durability,
+        operations: u64,
+        duration: Duration,
+        histogram: &Histogram,
+    ) {
+        let secs = duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9;
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.phase == phase && e.durability == durability)
+        {
+            Some(entry) => {
+                entry.operations += operations;
+                entry.secs += secs;
+                entry.histogram.merge(histogram);
+            }
+            None => self.entries.push(Entry {
+                phase: phase.to_string(),
+                durability,
+                operations,
+                secs,
+                histogram: histogram.clone(),
+            }),
+        }
+    }
+
+    pub fn print(&self) {
+        if self.durabilities.len() < 2 {
+            return;
+        }
+        let baseline = self.durabilities[0];
+        report!(
+            "Durability comparison (changes against {}):",
+            baseline.name()
+        );
+
+        let mut phases: Vec<&str> = Vec::new();
+        for entry in &self.entries {
+            if !phases.contains(&entry.phase.as_str()) {
+                phases.push(&entry.phase);
+            }
+        }
+        for phase in phases {
+            report!("  {}:", phase);
+            let entry = |durability| {
+                self.entries
+                    .iter()
+                    .find(|e| e.phase == phase && e.durability == durability)
+            };
+            let base = entry(baseline);
+            for &durability in &self.durabilities {
+                if let Some(e) = entry(durability) {
+                    print_entry(e, base.filter(|_| durability != baseline));
+                }
+            }
+        }
+    }
+}
+
+fn throughput(entry: &Entry) -> f64 {
+    if entry.secs > 0.0 {
+        entry.operations as f64 / entry.secs
+    } else {
+        0.0
+    }
+}
+
+// A value followed by its change from the baseline's, if there is one
+fn change(value: f64, base: Option<f64>) -> String {
+    match base {
+        Some(base) if base > 0.0 => format!(" ({:+.1}%)", (value - base) * 100.0 / base),
+        _ => String::new(),
+    }
+}
+
+fn print_entry(entry: &Entry, base: Option<&Entry>) {
+    let ops = throughput(entry);
+    let mut line = format!(
+        "    {}: {:.1} ops/s{}",
+        entry.durability.name(),
+        ops,
+        change(ops, base.map(throughput))
+    );
+    for &(name, percentile) in [("p50", 50.0), ("p99", 99.0)].iter() {
+        let value = entry.histogram.percentile(percentile) as f64;
+        let base_value = base.map(|b| b.histogram.percentile(percentile) as f64);
+        line.push_str(&format!(
+            " {}: {:.0} ns{}",
+            name,
+            value,
+            change(value, base_value)
+        ));
+    }
+    report!("{}", line);
+}
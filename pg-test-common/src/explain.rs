@@ -0,0 +1,178 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use postgres::types::ToSql;
+use postgres::Connection;
+use serde_json::{self, Value};
+
+use slug;
+
+// Only the first few flagged statements are printed to keep the output readable
+const MAX_REPORTED_WARNINGS: usize = 10;
+
+struct Explained {
+    name: String,
+    sql: String,
+    plan: Value,
+    missing_indexes: Vec<String>,
+    scans: Vec<String>,
+}
+
+/*
+ * Collects EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON) plans for the statements
+ * of a phase and writes them to `<dir>/<app>-<phase>.json`. Every statement
+ * is run in a transaction that is rolled back, so explaining a write doesn't
+ * change the data the phase starts with.
+ */
+pub struct Explainer {
+    dir: PathBuf,
+    app: String,
+    phase: String,
+    explained: Vec<Explained>,
+}
+
+impl Explainer {
+    pub fn new(dir: &str, app: &str, phase: &str) -> Explainer {
+        Explainer {
+            dir: PathBuf::from(dir),
+            app: app.to_string(),
+            phase: phase.to_string(),
+            explained: Vec::new(),
+        }
+    }
+
+    /*
+     * Explain a representative instance of a statement. Any index in
+     * `expected_indexes` that doesn't appear in the plan gets the statement
+     * flagged.
+     */
+    pub fn explain(
+        &mut self,
+        conn: &Connection,
+        name: &str,
+        sql: &str,
+        params: &[&dyn ToSql],
+        expected_indexes: &[&str],
+    ) {
+        let trans = conn.transaction().unwrap();
+        let explain_sql = ["EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON) ", sql].concat();
+        let rows = trans.query(explain_sql.as_str(), params).unwrap();
+        let plan: Value = rows.get(0).get(0);
+        trans.finish().unwrap();
+
+        let mut used_indexes = Vec::new();
+        collect_indexes(&plan, &mut used_indexes);
+        let missing_indexes = expected_indexes
+            .iter()
+            .filter(|index| !used_indexes.iter().any(|used| used == *index))
+            .map(|index| index.to_string())
+            .collect();
+
+        let mut scans = Vec::new();
+        collect_scans(&plan, &mut scans);
+
+        self.explained.push(Explained {
+            name: name.to_string(),
+            sql: sql.to_string(),
+            plan,
+            missing_indexes,
+            scans,
+        });
+    }
+
+    // Print a summary of the plans and write them out
+    pub fn finish(self) {
+        let flagged: Vec<&Explained> = self
+            .explained
+            .iter()
+            .filter(|e| !e.missing_indexes.is_empty())
+            .collect();
+
+        for explained in flagged.iter().take(MAX_REPORTED_WARNINGS) {
+            report!(
+                "WARNING: {} {} did not use expected index {} (plan: {})",
+                self.phase,
+                explained.name,
+                explained.missing_indexes.join(", "),
+                explained.scans.join(", ")
+            );
+        }
+
+        fs::create_dir_all(&self.dir).unwrap();
+        let path = self
+            .dir
+            .join(format!("{}-{}.json", self.app, slug(&self.phase)));
+        let plans: Vec<Value> = self
+            .explained
+            .iter()
+            .map(|e| {
+                json!({
+                    "name": e.name,
+                    "sql": e.sql,
+                    "missing_indexes": e.missing_indexes,
+                    "plan": e.plan,
+                })
+            })
+            .collect();
+        let mut file = File::create(&path).unwrap();
+        file.write_all(serde_json::to_string_pretty(&plans).unwrap().as_bytes())
+            .unwrap();
+
+        report!(
+            "{} EXPLAIN: {} statements, {} without their expected index, plans written to {}",
+            self.phase,
+            self.explained.len(),
+            flagged.len(),
+            path.display()
+        );
+    }
+}
+
+// Every index scanned or used as an ON CONFLICT arbiter anywhere in the plan
+fn collect_indexes(plan: &Value, indexes: &mut Vec<String>) {
+    match *plan {
+        Value::Object(ref node) => {
+            for (key, value) in node {
+                match (key.as_str(), value) {
+                    ("Index Name", Value::String(index)) => indexes.push(index.clone()),
+                    ("Conflict Arbiter Indexes", Value::Array(arbiters)) => {
+                        indexes.extend(arbiters.iter().filter_map(|a| a.as_str().map(String::from)))
+                    }
+                    _ => collect_indexes(value, indexes),
+                }
+            }
+        }
+        Value::Array(ref values) => {
+            for value in values {
+                collect_indexes(value, indexes);
+            }
+        }
+        _ => (),
+    }
+}
+
+// The scan nodes of the plan, e.g. "Seq Scan on person_count"
+fn collect_scans(plan: &Value, scans: &mut Vec<String>) {
+    match *plan {
+        Value::Object(ref node) => {
+            if let (Some(node_type), Some(relation)) = (
+                node.get("Node Type").and_then(Value::as_str),
+                node.get("Relation Name").and_then(Value::as_str),
+            ) {
+                if node_type.ends_with("Scan") {
+                    scans.push(format!("{} on {}", node_type, relation));
+                }
+            }
+            for value in node.values() {
+                collect_scans(value, scans);
+            }
+        }
+        Value::Array(ref values) => {
+            for value in values {
+                collect_scans(value, scans);
+            }
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use hdrhistogram;
+use hdrhistogram::serialization::interval_log::{IntervalLogWriterBuilder, Tag};
+use hdrhistogram::serialization::V2DeflateSerializer;
+
+use slug;
+use take_option;
+use take_parsed;
+
+const DEFAULT_PRECISION: usize = 3;
+const DEFAULT_MAX_LATENCY_MS: u64 = 60_000;
+const DEFAULT_LOG_INTERVAL_MS: u64 = 1000;
+
+/*
+ * How every histogram of the process is recorded: the number of significant
+ * figures kept for each sample and the largest sample, in nanoseconds, that
+ * can be recorded. Interval logging is off until a log directory is set.
+ */
+static PRECISION: AtomicUsize = AtomicUsize::new(DEFAULT_PRECISION);
+static MAX_NANOS: AtomicU64 = AtomicU64::new(DEFAULT_MAX_LATENCY_MS * 1_000_000);
+static LOG_INTERVAL_NANOS: AtomicU64 = AtomicU64::new(0);
+static LOG_DIR: Mutex<Option<String>> = Mutex::new(None);
+
+// Intervals are counted from the first histogram created, so that the
+// intervals of different threads line up when their histograms are merged
+static EPOCH: OnceLock<(Instant, SystemTime)> = OnceLock::new();
+
+/*
+ * Configure recording before any histogram is created. precision is the
+ * number of significant figures (1 to 5) and max_latency the largest
+ * latency that can be recorded; samples above it are counted as overflows.
+ * With a log directory each histogram also keeps its samples per interval of
+ * log_interval, and every phase writes them there as an HdrHistogram
+ * interval log.
+ */
+pub fn configure(
+    precision: Option<usize>,
+    max_latency: Option<Duration>,
+    log_dir: Option<String>,
+    log_interval: Option<Duration>,
+) {
+    if let Some(precision) = precision {
+        PRECISION.store(precision, Ordering::SeqCst);
+    }
+    if let Some(max_latency) = max_latency {
+        MAX_NANOS.store(nanos(max_latency), Ordering::SeqCst);
+    }
+    if log_dir.is_some() {
+        let interval = log_interval.unwrap_or_else(|| Duration::from_millis(DEFAULT_LOG_INTERVAL_MS));
+        LOG_INTERVAL_NANOS.store(nanos(interval).max(1), Ordering::SeqCst);
+    }
+    *LOG_DIR.lock().unwrap() = log_dir;
+
+    // Check the configuration now rather than in the first worker thread
+    Histogram::new();
+}
+
+/*
+ * Take --precision DIGITS, --max-latency MS, --histogram-log DIR and
+ * --log-interval MS off the command line and configure recording with them.
+ */
+pub fn configure_from_args(args: &mut Vec<String>) {
+    let precision = take_parsed(args, "--precision");
+    let max_latency = take_parsed(args, "--max-latency").map(Duration::from_millis);
+    let log_dir = take_option(args, "--histogram-log");
+    let log_interval = take_parsed(args, "--log-interval").map(Duration::from_millis);
+    configure(precision, max_latency, log_dir, log_interval);
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+fn epoch() -> &'static (Instant, SystemTime) {
+    EPOCH.get_or_init(|| (Instant::now(), SystemTime::now()))
+}
+
+pub(crate) fn new_hdr_histogram() -> hdrhistogram::Histogram<u64> {
+    let precision = PRECISION.load(Ordering::SeqCst);
+    let max = MAX_NANOS.load(Ordering::SeqCst);
+    hdrhistogram::Histogram::new_with_bounds(1, max.max(2), precision as u8).unwrap_or_else(|e| {
+        eprintln!("invalid histogram precision {} or maximum {} ns: {}", precision, max, e);
+        ::std::process::exit(1)
+    })
+}
+
+/*
+ * A latency histogram in nanoseconds. Samples larger than the configured
+ * maximum are counted rather than recorded. When interval logging is on the
+ * samples are also kept per interval, as counts by histogram bucket so an
+ * interval only costs memory for the buckets it actually used.
+ */
+#[derive(Clone)]
+pub struct Histogram {
+    histogram: hdrhistogram::Histogram<u64>,
+    overflows: u64,
+    intervals: BTreeMap<u64, BTreeMap<u64, u64>>,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        epoch();
+        Histogram {
+            histogram: new_hdr_histogram(),
+            overflows: 0,
+            intervals: BTreeMap::new(),
+        }
+    }
+
+    pub fn increment(&mut self, value: u64) {
+        self.increment_by(value, 1);
+    }
+
+    pub fn increment_by(&mut self, value: u64, count: u64) {
+        if self.histogram.record_n(value, count).is_err() {
+            self.overflows += count;
+            return;
+        }
+
+        let interval_nanos = LOG_INTERVAL_NANOS.load(Ordering::Relaxed);
+        if let Some(interval) = nanos(epoch().0.elapsed()).checked_div(interval_nanos) {
+            let bucket = self.histogram.lowest_equivalent(value);
+            *self
+                .intervals
+                .entry(interval)
+                .or_default()
+                .entry(bucket)
+                .or_insert(0) += count;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        self.histogram.add(&other.histogram).unwrap();
+        self.overflows += other.overflows;
+        for (&interval, buckets) in &other.intervals {
+            let merged = self.intervals.entry(interval).or_default();
+            for (&bucket, &count) in buckets {
+                *merged.entry(bucket).or_insert(0) += count;
+            }
+        }
+    }
+
+    // The number of samples recorded, not counting overflows
+    pub fn entries(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    pub fn overflows(&self) -> u64 {
+        self.overflows
+    }
+
+    // The largest sample that can be recorded
+    pub fn max_value(&self) -> u64 {
+        self.histogram.high()
+    }
+
+    // The value at a percentile, or 0 when nothing was recorded
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.entries() == 0 {
+            return 0;
+        }
+        self.histogram.value_at_quantile(percentile / 100.0)
+    }
+
+    // The number of samples in each bucket that has any, lowest value first
+    pub fn buckets(&self) -> Vec<(u64, u64)> {
+        self.histogram
+            .iter_recorded()
+            .map(|v| (self.histogram.lowest_equivalent(v.value_iterated_to()), v.count_at_value()))
+            .collect()
+    }
+}
+
+/*
+ * Write the interval samples of a phase's histograms to an HdrHistogram
+ * interval log named after the utility, phase and trial, with each
+ * histogram's intervals tagged by its operation. Nothing is written unless
+ * interval logging is on.
+ */
+pub fn write_log(app: &str, phase: &str, trial: usize, latencies: &[(&str, &Histogram)]) {
+    let dir = match *LOG_DIR.lock().unwrap() {
+        Some(ref dir) => dir.clone(),
+        None => return,
+    };
+    let interval = LOG_INTERVAL_NANOS.load(Ordering::SeqCst);
+    let path = Path::new(&dir).join(format!("{}-{}-{}.hlog", app, slug(phase), trial));
+
+    fs::create_dir_all(&dir).unwrap();
+    let mut file = BufWriter::new(File::create(&path).unwrap());
+    let mut serializer = V2DeflateSerializer::new();
+    let start_time = epoch().1;
+    let mut log = IntervalLogWriterBuilder::new()
+        .add_comment(&format!("{} {} trial {}, latencies in nanoseconds", app, phase, trial))
+        .with_start_time(start_time)
+        .with_base_time(start_time)
+        .with_max_value_divisor(1e6)
+        .begin_log_with(&mut file, &mut serializer)
+        .unwrap();
+
+    // Interleave the operations' intervals so the log is in timestamp order
+    let tags: Vec<String> = latencies.iter().map(|l| l.0.replace([' ', ','], "_")).collect();
+    let mut entries: Vec<(u64, &str, &BTreeMap<u64, u64>)> = Vec::new();
+    for (tag, &(_, histogram)) in tags.iter().zip(latencies) {
+        for (&index, buckets) in &histogram.intervals {
+            entries.push((index, tag, buckets));
+        }
+    }
+    entries.sort_by_key(|entry| entry.0);
+
+    for (index, tag, buckets) in entries {
+        let mut histogram = new_hdr_histogram();
+        for (&bucket, &count) in buckets {
+            histogram.record_n(bucket, count).unwrap();
+        }
+        log.write_histogram(
+            &histogram,
+            Duration::from_nanos(index * interval),
+            Duration::from_nanos(interval),
+            Tag::new(tag),
+        )
+        .unwrap();
+    }
+
+    report!("{} histogram log written to {}", phase, path.display());
+}
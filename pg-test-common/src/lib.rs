@@ -0,0 +1,93 @@
+extern crate clap;
+extern crate futures;
+extern crate hdrhistogram;
+extern crate postgres;
+extern crate rand;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate tokio;
+extern crate tokio_postgres;
+extern crate toml;
+
+// First, so that the modules below can use report!
+#[macro_use]
+pub mod report;
+pub mod activity;
+pub mod bloat;
+pub mod cli;
+pub mod durability;
+pub mod explain;
+pub mod histogram;
+pub mod locks;
+pub mod metrics;
+pub mod order;
+pub mod pipeline;
+pub mod replica;
+pub mod results;
+pub mod scenario;
+pub mod settings;
+pub mod statements;
+pub mod statistics;
+pub mod stats;
+pub mod sweep;
+pub mod work;
+
+use std::process;
+use std::str::FromStr;
+
+use postgres::{Connection, TlsMode};
+
+/*
+ * Connect to the server and tag the session with the benchmark's
+ * application_name so that its backends can be told apart from everything
+ * else in pg_stat_activity.
+ */
+pub fn connect(url: &str, application_name: &str) -> Connection {
+    let conn = Connection::connect(url, TlsMode::None).unwrap();
+    conn.execute("SELECT set_config('application_name', $1, false)", &[&application_name])
+        .unwrap();
+    conn
+}
+
+/*
+ * Remove a `--name VALUE` or `--name=VALUE` option from the command line so
+ * the remaining arguments can be parsed positionally.
+ */
+pub fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let index = args
+        .iter()
+        .position(|arg| arg == name || arg.starts_with(&prefix))?;
+    let arg = args.remove(index);
+
+    if arg != name {
+        return Some(arg[prefix.len()..].to_string());
+    }
+    if index < args.len() {
+        return Some(args.remove(index));
+    }
+
+    eprintln!("{} requires a value", name);
+    process::exit(1)
+}
+
+// Like take_option, for options whose value must parse as a T
+pub fn take_parsed<T: FromStr>(args: &mut Vec<String>, name: &str) -> Option<T> {
+    take_option(args, name).map(|value| {
+        value.parse().unwrap_or_else(|_| {
+            eprintln!("invalid value for {}: {}", name, value);
+            process::exit(1)
+        })
+    })
+}
+
+// A phase name made safe to use in a file name
+fn slug(phase: &str) -> String {
+    phase
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect()
+}
@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use connect;
+
+struct LockRow {
+    pid: i32,
+    locktype: String,
+    mode: String,
+    granted: bool,
+    relation: Option<String>,
+    query: String,
+}
+
+#[derive(Default)]
+pub struct RelationLocks {
+    pub blocked: u64,
+    pub longest_chain: usize,
+}
+
+/*
+ * What the benchmark's backends were blocked on across all samples. A
+ * blocked backend is one with an ungranted lock in pg_locks; the blocking
+ * chain is the longest sequence of backends each waiting on the next, as
+ * reported by pg_blocking_pids().
+ */
+#[derive(Default)]
+pub struct LockSummary {
+    pub samples: u64,
+    pub blocked: u64,
+    pub max_blocked: u64,
+    pub modes: BTreeMap<String, u64>,
+    pub relations: BTreeMap<String, RelationLocks>,
+}
+
+impl LockSummary {
+    pub fn print(&self, phase: &str) {
+        report!(
+            "{} lock waits ({} samples, {} blocked backend samples, max blocked: {}, mean blocked: {:.2}):",
+            phase,
+            self.samples,
+            self.blocked,
+            self.max_blocked,
+            if self.samples == 0 {
+                0.0
+            } else {
+                self.blocked as f64 / self.samples as f64
+            }
+        );
+
+        for (mode, count) in &self.modes {
+            report!("  mode {}: {}", mode, count);
+        }
+        for (relation, locks) in &self.relations {
+            report!(
+                "  relation {}: blocked: {} longest chain: {}",
+                relation, locks.blocked, locks.longest_chain
+            );
+        }
+    }
+
+    fn record(&mut self, rows: &[LockRow], blockers: &HashMap<i32, Vec<i32>>) {
+        self.samples += 1;
+
+        let waiting: Vec<&LockRow> = rows.iter().filter(|r| !r.granted).collect();
+        self.blocked += waiting.len() as u64;
+        self.max_blocked = self.max_blocked.max(waiting.len() as u64);
+
+        for lock in waiting {
+            let mode = format!("{} {}", lock.mode, lock.locktype);
+            *self.modes.entry(mode).or_insert(0) += 1;
+
+            let relation = waited_relation(lock, rows);
+            let chain = chain_length(lock.pid, blockers, &mut HashSet::new());
+            let relation_locks = self.relations.entry(relation).or_default();
+            relation_locks.blocked += 1;
+            relation_locks.longest_chain = relation_locks.longest_chain.max(chain);
+        }
+    }
+}
+
+// How much of the waiting statement to show when a lock has no relation
+const QUERY_SUMMARY_LENGTH: usize = 60;
+
+/*
+ * Row lock and unique index insertion waits show up as a wait on the
+ * holder's transactionid. They are attributed to the relation of the tuple
+ * lock the waiter already holds when there is one, and otherwise to the
+ * statement the waiter is running, which is enough to tell e.g. a
+ * person_count upsert from a person insert.
+ */
+fn waited_relation(lock: &LockRow, rows: &[LockRow]) -> String {
+    if let Some(ref relation) = lock.relation {
+        return relation.clone();
+    }
+
+    rows.iter()
+        .find(|r| r.pid == lock.pid && r.granted && r.locktype == "tuple")
+        .and_then(|r| r.relation.clone())
+        .unwrap_or_else(|| {
+            let query: Vec<&str> = lock.query.split_whitespace().collect();
+            let query: String = query.join(" ").chars().take(QUERY_SUMMARY_LENGTH).collect();
+            format!("{} ({})", lock.locktype, query)
+        })
+}
+
+// The number of backends in the longest chain of waiters ending at `pid`
+fn chain_length(pid: i32, blockers: &HashMap<i32, Vec<i32>>, seen: &mut HashSet<i32>) -> usize {
+    // A backend we have already visited means a deadlock cycle
+    if !seen.insert(pid) {
+        return 0;
+    }
+
+    let longest = blockers
+        .get(&pid)
+        .map(|pids| {
+            pids.iter()
+                .map(|&b| chain_length(b, blockers, seen))
+                .max()
+                .unwrap_or(0)
+        })
+        .unwrap_or(0);
+
+    seen.remove(&pid);
+    longest + 1
+}
+
+/*
+ * Capture the locks held and awaited by the backends tagged with
+ * `application_name` every `interval` until `stop` is set.
+ */
+pub fn spawn(
+    url: String,
+    application_name: String,
+    interval: Duration,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<LockSummary> {
+    thread::spawn(move || {
+        let conn = connect(&url, &[&application_name, "-locks"].concat());
+        let mut summary = LockSummary::default();
+
+        while !stop.load(Ordering::SeqCst) {
+            let result = conn
+                .query(
+                    "SELECT l.pid, l.locktype, l.mode, l.granted, l.relation::regclass::text, \
+                     pg_blocking_pids(l.pid), coalesce(a.query, '') \
+                     FROM pg_locks l JOIN pg_stat_activity a ON a.pid = l.pid \
+                     WHERE a.application_name = $1",
+                    &[&application_name],
+                )
+                .unwrap();
+
+            let mut rows = Vec::new();
+            let mut blockers = HashMap::new();
+            for row in result.iter() {
+                let pid: i32 = row.get(0);
+                let blocking_pids: Vec<i32> = row.get(5);
+                blockers.insert(pid, blocking_pids);
+                rows.push(LockRow {
+                    pid,
+                    locktype: row.get(1),
+                    mode: row.get(2),
+                    granted: row.get(3),
+                    relation: row.get(4),
+                    query: row.get(6),
+                });
+            }
+
+            summary.record(&rows, &blockers);
+
+            thread::sleep(interval);
+        }
+
+        summary
+    })
+}
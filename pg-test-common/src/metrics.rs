@@ -0,0 +1,466 @@
+use std::cell::RefCell;
+use std::error::Error as StdError;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hdrhistogram;
+use postgres;
+use tokio_postgres;
+
+use histogram::{new_hdr_histogram, Histogram};
+use take_option;
+use take_parsed;
+
+// How often the textfile for node_exporter is rewritten
+const TEXTFILE_INTERVAL: Duration = Duration::from_secs(5);
+
+static QUANTILES: [f64; 4] = [0.5, 0.9, 0.99, 0.999];
+
+// The classes errors are counted by, as named by sqlstate_class() and the callers
+static ERROR_CLASSES: [&str; 10] = [
+    "connection_exception",
+    "integrity_constraint_violation",
+    "transaction_rollback",
+    "insufficient_resources",
+    "object_not_in_prerequisite_state",
+    "operator_intervention",
+    "syntax_error_or_access_rule_violation",
+    "other_sqlstate",
+    "io",
+    "other",
+];
+
+/*
+ * One thread's share of a series. Only that thread records into it, so the
+ * worker threads never wait on each other to record a metric: the counters
+ * are atomics and the histogram's lock is only ever contended by render().
+ */
+struct Shard {
+    operations: AtomicU64,
+    errors: [AtomicU64; 10],
+    sum_nanos: AtomicU64,
+    histogram: Mutex<Option<hdrhistogram::Histogram<u64>>>,
+}
+
+impl Shard {
+    fn new() -> Shard {
+        Shard {
+            operations: AtomicU64::new(0),
+            errors: Default::default(),
+            sum_nanos: AtomicU64::new(0),
+            histogram: Mutex::new(Some(new_hdr_histogram())),
+        }
+    }
+
+    fn observe(&self, nanos: u64, count: u64) {
+        self.operations.fetch_add(count, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(nanos * count, Ordering::Relaxed);
+        if let Some(ref mut histogram) = *self.histogram.lock().unwrap() {
+            // Overflows are already reported with the phase's results
+            let _ = histogram.record_n(nanos, count);
+        }
+    }
+}
+
+/*
+ * The operations, errors and latencies of one operation of one phase, kept
+ * in a shard per thread that recorded any and only added up when the metrics
+ * are rendered. Only the phase that is running keeps histograms; once
+ * another phase starts the quantiles are frozen so that a long sweep doesn't
+ * keep a histogram for every phase it has run.
+ */
+struct Series {
+    mode: String,
+    phase: String,
+    operation: String,
+    shards: Vec<Arc<Shard>>,
+    quantiles: Option<Vec<u64>>,
+}
+
+impl Series {
+    fn labels(&self, app: &str) -> String {
+        format!(
+            "binary=\"{}\",mode=\"{}\",phase=\"{}\",operation=\"{}\"",
+            escape(app),
+            escape(&self.mode),
+            escape(&self.phase),
+            escape(&self.operation)
+        )
+    }
+
+    fn total(&self, counter: fn(&Shard) -> &AtomicU64) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| counter(shard).load(Ordering::Relaxed))
+            .sum()
+    }
+
+    fn freeze(&mut self) {
+        if self.quantiles.is_none() {
+            self.quantiles = Some(self.quantiles());
+            for shard in &self.shards {
+                *shard.histogram.lock().unwrap() = None;
+            }
+        }
+    }
+
+    fn quantiles(&self) -> Vec<u64> {
+        if let Some(ref quantiles) = self.quantiles {
+            return quantiles.clone();
+        }
+        let mut merged = new_hdr_histogram();
+        for shard in &self.shards {
+            if let Some(ref histogram) = *shard.histogram.lock().unwrap() {
+                let _ = merged.add(histogram);
+            }
+        }
+        QUANTILES.iter().map(|&q| merged.value_at_quantile(q)).collect()
+    }
+}
+
+struct Registry {
+    app: String,
+    mode: String,
+    phase: String,
+    series: Vec<Series>,
+    // The indexes of the running phase's series
+    current: Vec<usize>,
+}
+
+impl Registry {
+    // A new shard of the running phase's series of `operation`
+    fn shard(&mut self, operation: &str) -> Arc<Shard> {
+        let index = match self.current.iter().find(|&&i| self.series[i].operation == operation) {
+            Some(&index) => index,
+            None => {
+                let existing = self.series.iter().position(|s| {
+                    s.mode == self.mode && s.phase == self.phase && s.operation == operation
+                });
+                let index = existing.unwrap_or_else(|| {
+                    self.series.push(Series {
+                        mode: self.mode.clone(),
+                        phase: self.phase.clone(),
+                        operation: operation.to_string(),
+                        shards: Vec::new(),
+                        quantiles: None,
+                    });
+                    self.series.len() - 1
+                });
+                // A phase run again records its quantiles afresh
+                self.series[index].quantiles = None;
+                self.current.push(index);
+                index
+            }
+        };
+        let shard = Arc::new(Shard::new());
+        self.series[index].shards.push(Arc::clone(&shard));
+        shard
+    }
+
+    // The metrics in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str("# HELP pg_test_operations_total Operations completed.\n");
+        text.push_str("# TYPE pg_test_operations_total counter\n");
+        for series in &self.series {
+            let operations = series.total(|shard| &shard.operations);
+            let _ = writeln!(text, "pg_test_operations_total{{{}}} {}", series.labels(&self.app), operations);
+        }
+
+        text.push_str("# HELP pg_test_errors_total Operations that failed, by class of error.\n");
+        text.push_str("# TYPE pg_test_errors_total counter\n");
+        for series in &self.series {
+            for (index, class) in ERROR_CLASSES.iter().enumerate() {
+                let count: u64 = series
+                    .shards
+                    .iter()
+                    .map(|shard| shard.errors[index].load(Ordering::Relaxed))
+                    .sum();
+                if count == 0 {
+                    continue;
+                }
+                let _ = writeln!(
+                    text,
+                    "pg_test_errors_total{{{},class=\"{}\"}} {}",
+                    series.labels(&self.app),
+                    class,
+                    count
+                );
+            }
+        }
+
+        text.push_str("# HELP pg_test_latency_seconds Latency of completed operations.\n");
+        text.push_str("# TYPE pg_test_latency_seconds summary\n");
+        for series in &self.series {
+            let operations = series.total(|shard| &shard.operations);
+            if operations == 0 {
+                continue;
+            }
+            let labels = series.labels(&self.app);
+            for (quantile, nanos) in QUANTILES.iter().zip(series.quantiles()) {
+                let _ = writeln!(
+                    text,
+                    "pg_test_latency_seconds{{{},quantile=\"{}\"}} {}",
+                    labels,
+                    quantile,
+                    nanos as f64 / 1e9
+                );
+            }
+            let sum_nanos = series.total(|shard| &shard.sum_nanos);
+            let _ = writeln!(text, "pg_test_latency_seconds_sum{{{}}} {}", labels, sum_nanos as f64 / 1e9);
+            let _ = writeln!(text, "pg_test_latency_seconds_count{{{}}} {}", labels, operations);
+        }
+
+        text
+    }
+}
+
+/*
+ * The shards a thread records into, one per operation of the running phase.
+ * They are looked up again once the phase they belong to has ended.
+ */
+struct LocalShards {
+    phase: usize,
+    shards: Vec<(String, Arc<Shard>)>,
+}
+
+thread_local! {
+    static LOCAL_SHARDS: RefCell<LocalShards> = const {
+        RefCell::new(LocalShards {
+            phase: 0,
+            shards: Vec::new(),
+        })
+    };
+}
+
+// Metrics are only collected once an endpoint has been configured
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// Counts the phases started, so that threads know when their shards are stale
+static PHASE: AtomicUsize = AtomicUsize::new(0);
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+static TEXTFILE: Mutex<Option<String>> = Mutex::new(None);
+
+/*
+ * Expose live metrics while the utility runs: over HTTP on localhost:port,
+ * and/or in a textfile that node_exporter's textfile collector picks up.
+ * With neither, collecting metrics costs nothing.
+ */
+pub fn configure(app: &str, port: Option<u16>, textfile: Option<String>) {
+    if port.is_none() && textfile.is_none() {
+        return;
+    }
+
+    *REGISTRY.lock().unwrap() = Some(Registry {
+        app: app.to_string(),
+        mode: String::new(),
+        phase: String::new(),
+        series: Vec::new(),
+        current: Vec::new(),
+    });
+    ENABLED.store(true, Ordering::SeqCst);
+
+    if let Some(port) = port {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|e| {
+            eprintln!("unable to serve metrics on localhost:{}: {}", port, e);
+            process::exit(1)
+        });
+        report!("Serving metrics on http://localhost:{}/metrics", port);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                serve(stream);
+            }
+        });
+    }
+
+    if let Some(path) = textfile {
+        *TEXTFILE.lock().unwrap() = Some(path);
+        thread::spawn(|| loop {
+            thread::sleep(TEXTFILE_INTERVAL);
+            write_textfile();
+        });
+    }
+}
+
+// Take --metrics-port PORT and --metrics-textfile FILE off the command line
+pub fn configure_from_args(args: &mut Vec<String>, app: &str) {
+    let port = take_parsed(args, "--metrics-port");
+    let textfile = take_option(args, "--metrics-textfile");
+    configure(app, port, textfile);
+}
+
+// Label everything recorded from now on with the mode and phase
+pub fn start_phase(mode: &str, phase: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut registry = REGISTRY.lock().unwrap();
+    let registry = registry.as_mut().unwrap();
+    for &index in &registry.current {
+        registry.series[index].freeze();
+    }
+    registry.current.clear();
+    registry.mode = mode.to_string();
+    registry.phase = phase.to_string();
+    PHASE.fetch_add(1, Ordering::SeqCst);
+}
+
+/*
+ * Record into this thread's shard of the running phase's series of
+ * `operation`. The registry is only locked the first time a thread records
+ * an operation in a phase.
+ */
+fn with_shard<F: FnOnce(&Shard)>(operation: &str, f: F) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let phase = PHASE.load(Ordering::SeqCst);
+    LOCAL_SHARDS.with(|local| {
+        let mut local = local.borrow_mut();
+        if local.phase != phase {
+            local.phase = phase;
+            local.shards.clear();
+        }
+        let index = match local.shards.iter().position(|s| s.0 == operation) {
+            Some(index) => index,
+            None => {
+                let shard = REGISTRY.lock().unwrap().as_mut().unwrap().shard(operation);
+                local.shards.push((operation.to_string(), shard));
+                local.shards.len() - 1
+            }
+        };
+        f(&local.shards[index].1)
+    });
+}
+
+// Count `count` operations that each took `nanos`
+pub fn observe(operation: &str, nanos: u64, count: u64) {
+    with_shard(operation, |shard| shard.observe(nanos, count));
+}
+
+pub fn error(operation: &str, error: &postgres::Error) {
+    let class = match error.code() {
+        Some(state) => sqlstate_class(state.code()),
+        None if error.as_io().is_some() => "io",
+        None => "other",
+    };
+    count_error(operation, class);
+}
+
+// Like error(), for the errors of the async engine
+pub fn async_error(operation: &str, error: &tokio_postgres::Error) {
+    let class = match error.code() {
+        Some(state) => sqlstate_class(state.code()),
+        None if error.source().is_some_and(|e| e.is::<io::Error>()) => "io",
+        None => "other",
+    };
+    count_error(operation, class);
+}
+
+fn count_error(operation: &str, class: &'static str) {
+    let index = ERROR_CLASSES.iter().position(|&c| c == class).unwrap();
+    with_shard(operation, |shard| {
+        shard.errors[index].fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+/*
+ * Run an operation, recording its latency in `histogram` if it succeeds. A
+ * failed operation is counted by its class of error instead and the caller
+ * carries on with the next one.
+ */
+pub fn timed<T, F>(operation: &str, histogram: &mut Histogram, f: F) -> Option<T>
+where
+    F: FnOnce() -> postgres::Result<T>,
+{
+    let start = Instant::now();
+    match f() {
+        Ok(value) => {
+            let elapsed = start.elapsed();
+            let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+            histogram.increment(nanos);
+            observe(operation, nanos, 1);
+            Some(value)
+        }
+        Err(e) => {
+            error(operation, &e);
+            None
+        }
+    }
+}
+
+// Write the textfile one last time so it holds the final counts
+pub fn finish() {
+    if ENABLED.load(Ordering::Relaxed) {
+        write_textfile();
+    }
+}
+
+/*
+ * The SQLSTATE class of a server error, named after the classes that
+ * contention and connection trouble produce. Errors that never reached the
+ * server are counted as io errors by the callers.
+ */
+fn sqlstate_class(state: &str) -> &'static str {
+    match &state[..2] {
+        "08" => "connection_exception",
+        "23" => "integrity_constraint_violation",
+        "40" => "transaction_rollback",
+        "53" => "insufficient_resources",
+        "55" => "object_not_in_prerequisite_state",
+        "57" => "operator_intervention",
+        "42" => "syntax_error_or_access_rule_violation",
+        _ => "other_sqlstate",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn render() -> String {
+    REGISTRY.lock().unwrap().as_ref().map(Registry::render).unwrap_or_default()
+}
+
+fn serve(mut stream: TcpStream) {
+    // The request itself doesn't matter, every path gets the metrics
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request);
+
+    let body = render();
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+}
+
+// node_exporter may read the file at any time, so it is replaced atomically
+fn write_textfile() {
+    let path = match *TEXTFILE.lock().unwrap() {
+        Some(ref path) => path.clone(),
+        None => return,
+    };
+    let temp = format!("{}.tmp", path);
+
+    let written = File::create(&temp)
+        .and_then(|mut file| file.write_all(render().as_bytes()))
+        .and_then(|_| fs::rename(&temp, &path));
+    if let Err(e) = written {
+        eprintln!("unable to write metrics to {}: {}", path, e);
+    }
+}
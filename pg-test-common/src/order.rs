@@ -0,0 +1,47 @@
+use std::str::FromStr;
+
+use rand::{thread_rng, Rng};
+
+/*
+ * The order the phases of a run are executed in on each trial. Running them
+ * in the same order every time lets whatever the earlier phases leave behind
+ * (a warm cache, a pending checkpoint, a larger table) count against the
+ * later ones. Alternating reverses the order on every other trial so each
+ * phase runs first as often as last, and random shuffles it every trial.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Order {
+    Fixed,
+    Alternate,
+    Random,
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Order::Fixed),
+            "alternate" => Ok(Order::Alternate),
+            "random" => Ok(Order::Random),
+            _ => Err(format!("invalid order: {}", s)),
+        }
+    }
+}
+
+impl Order {
+    // The phases to run on the given (zero based) trial
+    pub fn arrange<T: Clone>(self, phases: &[T], trial: u32) -> Vec<T> {
+        let mut arranged = phases.to_vec();
+        match self {
+            Order::Fixed => (),
+            Order::Alternate => {
+                if trial % 2 == 1 {
+                    arranged.reverse();
+                }
+            }
+            Order::Random => thread_rng().shuffle(&mut arranged),
+        }
+        arranged
+    }
+}
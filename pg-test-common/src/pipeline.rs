@@ -0,0 +1,100 @@
+use std::process;
+use std::time::Instant;
+
+use futures::future::{self, Future, FutureExt};
+use tokio::runtime::Builder;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Config, NoTls};
+
+use histogram::Histogram;
+use metrics;
+use work::Work;
+
+pub use tokio::runtime::Runtime;
+pub use tokio_postgres::{Client, Error, Statement};
+
+/*
+ * The async engine drives many concurrent operations over a few connections
+ * from one OS thread rather than giving every operation a thread and a
+ * connection of its own. tokio-postgres pipelines the statements of every
+ * operation in flight on a connection, sending each without waiting for the
+ * results of those before it.
+ *
+ * Everything runs on a single threaded runtime inside block_on, so the
+ * futures of a phase can borrow its clients, statements and histograms.
+ */
+
+// The parameters of a statement, owned so that it can wait in a pipeline
+pub type Params = Vec<Box<dyn ToSql + Sync + Send>>;
+
+pub fn param<T: ToSql + Sync + Send + 'static>(value: T) -> Box<dyn ToSql + Sync + Send> {
+    Box::new(value)
+}
+
+pub fn runtime() -> Runtime {
+    Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+/*
+ * Open `count` connections, tagged with the application_name the way
+ * connect() tags its sessions. Each connection is driven by a task of the
+ * runtime, so they only make progress inside its block_on.
+ */
+pub fn connect(runtime: &Runtime, url: &str, application_name: &str, count: u32) -> Vec<Client> {
+    let mut config: Config = url.parse().unwrap_or_else(|e| {
+        eprintln!("invalid Postgres URL {}: {}", url, e);
+        process::exit(1)
+    });
+    config.application_name(application_name);
+
+    let connects = (0..count).map(|_| config.connect(NoTls));
+    runtime
+        .block_on(future::join_all(connects))
+        .into_iter()
+        .map(|connected| {
+            let (client, connection) = connected.unwrap_or_else(|e| {
+                eprintln!("Postgres connection error: {}", e);
+                process::exit(1)
+            });
+            runtime.spawn(connection);
+            client
+        })
+        .collect()
+}
+
+/*
+ * Time an operation from when it is first polled, which is when its
+ * statement joins the pipeline, until its results come back. Its latency is
+ * observed by the metrics when it succeeds and its error counted when it
+ * fails, and either way the caller gets what to record with record().
+ */
+pub fn timed<T, F>(operation: &'static str, f: F) -> impl Future<Output = Option<(T, u64)>>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    future::lazy(|_| Instant::now()).then(move |start| {
+        f.map(move |result| match result {
+            Ok(value) => {
+                let elapsed = start.elapsed();
+                let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+                metrics::observe(operation, nanos, 1);
+                Some((value, nanos))
+            }
+            Err(e) => {
+                metrics::async_error(operation, &e);
+                None
+            }
+        })
+    })
+}
+
+// Count a timed operation that wrote `rows` in `work` and its latency in `histogram`
+pub fn record<T>(work: &mut Work, histogram: &mut Histogram, rows: u64, outcome: Option<(T, u64)>) {
+    match outcome {
+        Some((_, nanos)) => {
+            histogram.increment(nanos);
+            work.commit(rows);
+        }
+        None => work.fail(),
+    }
+}
@@ -0,0 +1,200 @@
+use std::process;
+use std::sync::mpsc::{self, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use postgres::{self, Connection, TlsMode};
+
+use histogram::Histogram;
+use metrics;
+
+// How often a replica is polled for a write it doesn't show yet
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+// A write not visible by then is given up on, as the standby may have stopped replaying
+const VISIBILITY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/*
+ * How far reads on a replica lagged behind the writes on the primary: of the
+ * writes checked on the replica right after they committed, how many it
+ * didn't show yet, and how long after the commit those became visible.
+ */
+#[derive(Clone, Default)]
+pub struct Lag {
+    pub checked: u64,
+    pub stale: u64,
+    pub timed_out: u64,
+    pub visible_after: Histogram,
+}
+
+impl Lag {
+    // Count a write the replica didn't show at first, but does now
+    fn visible(&mut self, committed: Instant) {
+        self.checked += 1;
+        self.stale += 1;
+        let elapsed = committed.elapsed();
+        let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+        self.visible_after.increment(nanos);
+        metrics::observe("Replica lag", nanos, 1);
+    }
+
+    fn timed_out(&mut self) {
+        self.checked += 1;
+        self.stale += 1;
+        self.timed_out += 1;
+    }
+
+    pub fn merge(&mut self, other: &Lag) {
+        self.checked += other.checked;
+        self.stale += other.stale;
+        self.timed_out += other.timed_out;
+        self.visible_after.merge(&other.visible_after);
+    }
+
+    pub fn print(&self, phase: &str) {
+        let percent = if self.checked > 0 {
+            self.stale as f64 * 100.0 / self.checked as f64
+        } else {
+            0.0
+        };
+        report!(
+            "{} replica reads: {} writes checked, {} not yet visible ({:.1}%), {} not visible after {:?}",
+            phase, self.checked, self.stale, percent, self.timed_out, VISIBILITY_TIMEOUT
+        );
+        if self.visible_after.entries() > 0 {
+            report!(
+                "{} replica lag until visible: p50: {} ns p90: {} ns p99: {} ns p999: {} ns",
+                phase,
+                self.visible_after.percentile(50.0),
+                self.visible_after.percentile(90.0),
+                self.visible_after.percentile(99.0),
+                self.visible_after.percentile(99.9)
+            );
+        }
+    }
+}
+
+/*
+ * Connect to a replica, which has to be a standby in recovery: reads of a
+ * server that isn't replaying the primary's writes would never see them. The
+ * session is tagged with the application_name as connect() does.
+ */
+pub fn connect(url: &str, application_name: &str) -> Connection {
+    let conn = Connection::connect(url, TlsMode::None).unwrap_or_else(|e| {
+        eprintln!("Postgres replica connection error: {}", e);
+        process::exit(1)
+    });
+    conn.execute("SELECT set_config('application_name', $1, false)", &[&application_name])
+        .unwrap();
+    let in_recovery: bool = conn.query("SELECT pg_is_in_recovery()", &[]).unwrap().get(0).get(0);
+    if !in_recovery {
+        eprintln!("--replica-url {} is not a standby in recovery", url);
+        process::exit(1);
+    }
+    conn
+}
+
+/*
+ * Whether each of the transactions `xids`, all committed on the primary, is
+ * visible to a snapshot taken on the replica now. Transactions the replica
+ * hasn't replayed the commit of yet are still running as far as it knows.
+ */
+fn visible(replica: &Connection, xids: &[i64]) -> postgres::Result<Vec<bool>> {
+    let rows = replica.query(
+        "SELECT txid_visible_in_snapshot(xid, txid_current_snapshot()) \
+         FROM unnest($1::bigint[]) WITH ORDINALITY AS t (xid, n) ORDER BY n",
+        &[&xids.to_vec()],
+    )?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// A write on the primary waiting to be seen on the replica
+struct Pending {
+    xid: i64,
+    committed: Instant,
+    checked: bool,
+}
+
+/*
+ * Check writes on the replica as the writers send them: the id of the
+ * transaction of each write (from txid_current()) and when it committed.
+ * The checks run on their own thread, so that polling the replica doesn't
+ * slow the writers down, and look for every write sent so far with a single
+ * query. A write that isn't visible the first time it is looked for is
+ * counted as stale, and is looked for again until it becomes visible. The
+ * lag is returned once every sender has been dropped and every write has
+ * been seen or given up on.
+ */
+pub fn spawn_checker(replica: Connection) -> (Sender<(i64, Instant)>, JoinHandle<Lag>) {
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let mut lag = Lag::default();
+        let mut pending: Vec<Pending> = Vec::new();
+        let mut open = true;
+
+        while open || !pending.is_empty() {
+            // Wait for a write when there is none to look for
+            if pending.is_empty() {
+                match receiver.recv() {
+                    Ok((xid, committed)) => pending.push(Pending { xid, committed, checked: false }),
+                    Err(_) => break,
+                }
+            }
+            loop {
+                match receiver.try_recv() {
+                    Ok((xid, committed)) => pending.push(Pending { xid, committed, checked: false }),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        open = false;
+                        break;
+                    }
+                }
+            }
+
+            let xids: Vec<i64> = pending.iter().map(|p| p.xid).collect();
+            let seen = match visible(&replica, &xids) {
+                Ok(seen) => seen,
+                Err(e) => {
+                    metrics::error("Replica read", &e);
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+            let mut waiting = Vec::new();
+            for (write, seen) in pending.drain(..).zip(seen) {
+                if seen && !write.checked {
+                    lag.checked += 1;
+                } else if seen {
+                    lag.visible(write.committed);
+                } else if write.committed.elapsed() > VISIBILITY_TIMEOUT {
+                    lag.timed_out();
+                } else {
+                    waiting.push(Pending { checked: true, ..write });
+                }
+            }
+            pending = waiting;
+            if !pending.is_empty() {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        lag
+    });
+    (sender, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let mut lag = Lag::default();
+        lag.checked += 1;
+        let mut other = Lag::default();
+        other.visible(Instant::now());
+        other.timed_out();
+
+        lag.merge(&other);
+        assert_eq!((lag.checked, lag.stale, lag.timed_out), (3, 2, 1));
+        assert_eq!(lag.visible_after.entries(), 1);
+    }
+}
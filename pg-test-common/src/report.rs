@@ -0,0 +1,34 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/*
+ * Where the reports a run prints as it goes, such as each phase's throughput
+ * and latencies or the server statistics around it, are written. They go to
+ * stdout unless it is kept for the JSON results of `--format json`, in which
+ * case they go to stderr.
+ */
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+// Send every report from now on to stderr
+pub fn to_stderr() {
+    TO_STDERR.store(true, Ordering::SeqCst);
+}
+
+pub fn print(args: fmt::Arguments) {
+    if TO_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", args);
+    } else {
+        println!("{}", args);
+    }
+}
+
+// Like println!, for a report
+#[macro_export]
+macro_rules! report {
+    () => {
+        $crate::report::print(format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::report::print(format_args!($($arg)*))
+    };
+}
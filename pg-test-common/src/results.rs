@@ -0,0 +1,708 @@
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use histogram::{self, Histogram};
+use report;
+use serde_json::{self, Map, Value};
+
+use settings::Settings;
+use statistics::{MannWhitney, Summary};
+use sweep::Point;
+use take_parsed;
+use take_option;
+
+// The percentiles recorded for every latency histogram
+static PERCENTILES: [(&str, f64); 4] = [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("p999", 99.9)];
+
+// The relative change, in percent, beyond which a difference is a regression
+const DEFAULT_THRESHOLD: f64 = 10.0;
+
+// The p value below which a difference in latency is reported as significant
+const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+struct Phase {
+    name: String,
+    // The values of the swept parameters the phase ran with, if any
+    point: Vec<(String, u32)>,
+    trials: Vec<Value>,
+    // Every trial's latency samples, merged by operation
+    histograms: Vec<(String, Histogram)>,
+}
+
+impl Phase {
+    fn label(&self) -> String {
+        label(&self.name, &self.point)
+    }
+}
+
+// A phase's name followed by the sweep point it ran with, if any
+fn label(name: &str, point: &[(String, u32)]) -> String {
+    if point.is_empty() {
+        return name.to_string();
+    }
+    let point: Vec<String> = point
+        .iter()
+        .map(|&(ref name, value)| format!("{}={}", name, value))
+        .collect();
+    format!("{} [{}]", name, point.join(" "))
+}
+
+/*
+ * The results of a run: the throughput of each phase and the percentiles of
+ * each of its latency histograms, along with the command line that produced
+ * them and the session settings the workers ran with. A phase that is run
+ * more than once is summarized over its trials, and the mean of each number
+ * is what gets saved and compared. In a sweep every phase is recorded
+ * separately for each point.
+ */
+pub struct Results {
+    app: String,
+    settings: Value,
+    point: Vec<(String, u32)>,
+    phases: Vec<Phase>,
+}
+
+impl Results {
+    pub fn new(app: &str, settings: &Settings) -> Results {
+        Results {
+            app: app.to_string(),
+            settings: settings.to_json(),
+            point: Vec::new(),
+            phases: Vec::new(),
+        }
+    }
+
+    // The sweep point the phases added from now on ran with
+    pub fn set_point(&mut self, point: &Point) {
+        self.point = point
+            .swept()
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value))
+            .collect();
+    }
+
+    // How a phase running at the current sweep point is labelled
+    pub fn label(&self, phase: &str) -> String {
+        label(phase, &self.point)
+    }
+
+    // Record a trial of a phase. Trials are grouped by phase name and point.
+    pub fn add_phase(
+        &mut self,
+        phase: &str,
+        operations: u64,
+        duration: Duration,
+        latencies: &[(&str, &Histogram)],
+    ) {
+        let secs = duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9;
+        let mut histograms = Map::new();
+        for &(operation, histogram) in latencies {
+            if histogram.overflows() > 0 {
+                report!(
+                    "WARNING: {} {}: {} samples above the maximum latency of {} ns were not recorded",
+                    phase,
+                    operation,
+                    histogram.overflows(),
+                    histogram.max_value()
+                );
+            }
+            if histogram.entries() == 0 {
+                continue;
+            }
+            let mut percentiles = Map::new();
+            percentiles.insert("samples".to_string(), json!(histogram.entries()));
+            percentiles.insert("overflows".to_string(), json!(histogram.overflows()));
+            for &(name, percentile) in PERCENTILES.iter() {
+                percentiles.insert(name.to_string(), json!(histogram.percentile(percentile)));
+            }
+            histograms.insert(operation.to_string(), Value::Object(percentiles));
+        }
+
+        let trial = json!({
+            "operations": operations,
+            "duration_secs": secs,
+            "throughput": if secs > 0.0 { operations as f64 / secs } else { 0.0 },
+            "latencies": histograms,
+        });
+
+        let point = &self.point;
+        let index = match self.phases.iter().position(|p| p.name == phase && p.point == *point) {
+            Some(index) => index,
+            None => {
+                self.phases.push(Phase {
+                    name: phase.to_string(),
+                    point: point.clone(),
+                    trials: Vec::new(),
+                    histograms: Vec::new(),
+                });
+                self.phases.len() - 1
+            }
+        };
+        let phase = &mut self.phases[index];
+        phase.trials.push(trial);
+        histogram::write_log(&self.app, &phase.label(), phase.trials.len(), latencies);
+        for &(operation, histogram) in latencies {
+            match phase.histograms.iter_mut().find(|h| h.0 == operation) {
+                Some(merged) => merged.1.merge(histogram),
+                None => phase.histograms.push((operation.to_string(), histogram.clone())),
+            }
+        }
+    }
+
+    /*
+     * Print the mean, standard deviation and 95% confidence interval of the
+     * throughput and every latency percentile of each phase run more than
+     * once.
+     */
+    pub fn print_summary(&self) {
+        for phase in self.phases.iter().filter(|p| p.trials.len() > 1) {
+            report!("{} over {} trials:", phase.label(), phase.trials.len());
+            print_summary_line("throughput", "ops/s", &trial_values(&phase.trials, &["throughput"]));
+
+            for operation in operations(&phase.trials) {
+                for &(percentile, _) in PERCENTILES.iter() {
+                    let values = trial_values(&phase.trials, &["latencies", &operation, percentile]);
+                    print_summary_line(&[&operation, " ", percentile].concat(), "ns", &values);
+                }
+            }
+        }
+
+        self.print_significance();
+        if let Some(table) = self.table() {
+            print_table(&table);
+        }
+    }
+
+    /*
+     * Compare the latency samples of every pair of phases that recorded the
+     * same operation at the same point, pooling the samples of all their
+     * trials, and report whether one is significantly slower than the other.
+     */
+    fn print_significance(&self) {
+        let mut header = false;
+        for (i, a) in self.phases.iter().enumerate() {
+            for b in self.phases[i + 1..].iter().filter(|b| b.point == a.point) {
+                for (operation, a_histogram) in &a.histograms {
+                    let b_histogram = match b.histograms.iter().find(|h| h.0 == *operation) {
+                        Some((_, histogram)) => histogram,
+                        None => continue,
+                    };
+                    let test = match MannWhitney::new(a_histogram, b_histogram) {
+                        Some(test) => test,
+                        None => continue,
+                    };
+
+                    if !header {
+                        report!("Latency differences (Mann-Whitney U, significance level {}):",
+                                 SIGNIFICANCE_LEVEL);
+                        header = true;
+                    }
+                    let (slower, faster, fraction) = if test.a_slower >= 0.5 {
+                        (a.label(), b.label(), test.a_slower)
+                    } else {
+                        (b.label(), a.label(), 1.0 - test.a_slower)
+                    };
+                    report!(
+                        "  {}: {} slower than {} in {:.1}% of sample pairs z: {:.2} p: {:.4} ({})",
+                        operation,
+                        slower,
+                        faster,
+                        fraction * 100.0,
+                        test.z,
+                        test.p,
+                        if test.p < SIGNIFICANCE_LEVEL { "significant" } else { "not significant" }
+                    );
+                }
+            }
+        }
+    }
+
+    /*
+     * The consolidated results of a sweep: a header row followed by one row
+     * per phase and point with the swept parameters' values, the throughput
+     * and the percentiles of every latency histogram, averaged over trials.
+     */
+    fn table(&self) -> Option<Vec<Vec<String>>> {
+        let parameters: Vec<String> = match self.phases.iter().find(|p| !p.point.is_empty()) {
+            Some(phase) => phase.point.iter().map(|p| p.0.clone()).collect(),
+            None => return None,
+        };
+        let mut operations: Vec<String> = Vec::new();
+        for phase in &self.phases {
+            for operation in self::operations(&phase.trials) {
+                if !operations.contains(&operation) {
+                    operations.push(operation);
+                }
+            }
+        }
+
+        let mut header = parameters.clone();
+        header.push("phase".to_string());
+        header.push("ops/s".to_string());
+        for operation in &operations {
+            for &(percentile, _) in PERCENTILES.iter() {
+                header.push(format!("{} {} ns", operation, percentile));
+            }
+        }
+
+        let mut rows = vec![header];
+        for phase in &self.phases {
+            let mut row: Vec<String> = phase.point.iter().map(|p| p.1.to_string()).collect();
+            row.push(phase.name.clone());
+            let throughput = Summary::new(&trial_values(&phase.trials, &["throughput"]));
+            row.push(format!("{:.1}", throughput.mean));
+            for operation in &operations {
+                for &(percentile, _) in PERCENTILES.iter() {
+                    let values = trial_values(&phase.trials, &["latencies", operation, percentile]);
+                    row.push(if values.is_empty() {
+                        "-".to_string()
+                    } else {
+                        format!("{:.0}", Summary::new(&values).mean)
+                    });
+                }
+            }
+            rows.push(row);
+        }
+
+        Some(rows)
+    }
+
+    fn to_json(&self) -> Value {
+        let phases: Vec<Value> = self
+            .phases
+            .iter()
+            .map(|phase| {
+                let mut latencies = Map::new();
+                for operation in operations(&phase.trials) {
+                    let mut percentiles = Map::new();
+                    for &(percentile, _) in PERCENTILES.iter() {
+                        let values = trial_values(&phase.trials, &["latencies", &operation, percentile]);
+                        percentiles.insert(percentile.to_string(), json!(Summary::new(&values).mean));
+                    }
+                    latencies.insert(operation, Value::Object(percentiles));
+                }
+
+                let throughput = Summary::new(&trial_values(&phase.trials, &["throughput"]));
+                let mut json = json!({
+                    "name": phase.name,
+                    "operations": Summary::new(&trial_values(&phase.trials, &["operations"])).mean,
+                    "duration_secs": Summary::new(&trial_values(&phase.trials, &["duration_secs"])).mean,
+                    "throughput": throughput.mean,
+                    "throughput_stddev": throughput.stddev,
+                    "throughput_ci": throughput.ci,
+                    "latencies": latencies,
+                    "trials": phase.trials,
+                });
+                if !phase.point.is_empty() {
+                    let point: Map<String, Value> = phase
+                        .point
+                        .iter()
+                        .map(|&(ref name, value)| (name.clone(), json!(value)))
+                        .collect();
+                    json["point"] = Value::Object(point);
+                }
+                json
+            })
+            .collect();
+
+        json!({
+            "app": self.app,
+            "args": env::args().collect::<Vec<String>>(),
+            "settings": self.settings,
+            "phases": phases,
+        })
+    }
+}
+
+// The latency histograms recorded by any trial of a phase
+fn operations(trials: &[Value]) -> Vec<String> {
+    let mut operations: Vec<String> = Vec::new();
+    for trial in trials {
+        if let Some(latencies) = trial["latencies"].as_object() {
+            for operation in latencies.keys() {
+                if !operations.contains(operation) {
+                    operations.push(operation.clone());
+                }
+            }
+        }
+    }
+    operations
+}
+
+// The value at `path` in every trial that has one
+fn trial_values(trials: &[Value], path: &[&str]) -> Vec<f64> {
+    trials
+        .iter()
+        .filter_map(|trial| path.iter().fold(trial, |value, key| &value[*key]).as_f64())
+        .collect()
+}
+
+fn print_table(table: &[Vec<String>]) {
+    let mut widths = vec![0; table[0].len()];
+    for row in table {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    report!("Sweep results:");
+    for row in table {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:>1$}", cell, width))
+            .collect();
+        report!("  {}", cells.join("  "));
+    }
+}
+
+fn print_summary_line(label: &str, unit: &str, values: &[f64]) {
+    if values.is_empty() {
+        return;
+    }
+    let summary = Summary::new(values);
+    report!(
+        "  {}: mean: {:.1} {} stddev: {:.1} 95% CI: [{:.1}, {:.1}] ({} trials)",
+        label,
+        summary.mean,
+        unit,
+        summary.stddev,
+        summary.mean - summary.ci,
+        summary.mean + summary.ci,
+        summary.n
+    );
+}
+
+/*
+ * How the results are printed at the end of a run: as text, or as the same
+ * JSON --save writes so that scripts can read it from stdout. With JSON
+ * every report, including the summary of the trials, goes to stderr instead.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("invalid format: {}", s)),
+        }
+    }
+}
+
+/*
+ * Where a run's results go: saved to a file for later runs to compare
+ * against, and/or compared with the results of an earlier run.
+ */
+pub struct Output {
+    save: Option<String>,
+    compare: Option<String>,
+    threshold: f64,
+    table: Option<String>,
+    format: Format,
+}
+
+impl Output {
+    pub fn new(
+        save: Option<String>,
+        compare: Option<String>,
+        threshold: Option<f64>,
+        table: Option<String>,
+        format: Option<Format>,
+    ) -> Output {
+        let format = format.unwrap_or(Format::Text);
+        if format == Format::Json {
+            report::to_stderr();
+        }
+        Output {
+            save,
+            compare,
+            threshold: threshold.unwrap_or(DEFAULT_THRESHOLD),
+            table,
+            format,
+        }
+    }
+
+    /*
+     * Take --save FILE, --compare FILE, --threshold PCT, --table FILE and
+     * --format FORMAT off the command line.
+     */
+    pub fn from_args(args: &mut Vec<String>) -> Output {
+        let save = take_option(args, "--save");
+        let compare = take_option(args, "--compare");
+        let threshold = take_parsed(args, "--threshold");
+        let table = take_option(args, "--table");
+        let format = take_parsed(args, "--format");
+        Output::new(save, compare, threshold, table, format)
+    }
+
+    /*
+     * Save, compare and print the results. A run that regressed beyond the
+     * threshold against the results it was compared with exits with status 1
+     * once everything else is done, so that scripts can tell.
+     */
+    pub fn finish(&self, results: &Results) {
+        results.print_summary();
+        let current = results.to_json();
+        let mut regressions = 0;
+
+        if let Some(ref path) = self.save {
+            let mut file = File::create(path).unwrap();
+            file.write_all(serde_json::to_string_pretty(&current).unwrap().as_bytes())
+                .unwrap();
+            report!("Results saved to {}", path);
+        }
+
+        if let Some(ref path) = self.table {
+            match results.table() {
+                Some(table) => {
+                    let csv: Vec<String> = table.iter().map(|row| row.join(",")).collect();
+                    let mut file = File::create(path).unwrap();
+                    file.write_all(csv.join("\n").as_bytes()).unwrap();
+                    file.write_all(b"\n").unwrap();
+                    report!("Sweep table written to {}", path);
+                }
+                None => eprintln!("--table only applies to sweeps, {} not written", path),
+            }
+        }
+
+        if let Some(ref path) = self.compare {
+            let mut contents = String::new();
+            File::open(path)
+                .and_then(|mut file| file.read_to_string(&mut contents))
+                .unwrap_or_else(|e| {
+                    eprintln!("unable to read {}: {}", path, e);
+                    process::exit(1)
+                });
+            let baseline: Value = serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("invalid results in {}: {}", path, e);
+                process::exit(1)
+            });
+            regressions = compare(&current, &baseline, path, self.threshold);
+        }
+
+        if self.format == Format::Json {
+            println!("{}", serde_json::to_string_pretty(&current).unwrap());
+        }
+        if regressions > 0 {
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ * Print the relative change of every phase's throughput and latency
+ * percentiles against the baseline, and return the number of regressions:
+ * drops in throughput or rises in latency larger than the threshold.
+ */
+fn compare(current: &Value, baseline: &Value, path: &str, threshold: f64) -> u32 {
+    report!("Comparison with {} (regression threshold: {}%):", path, threshold);
+    if baseline["app"] != current["app"] {
+        report!(
+            "  WARNING: {} holds results of {}",
+            path,
+            baseline["app"].as_str().unwrap_or("an unknown utility")
+        );
+    }
+    // Results saved before settings were recorded ran without any
+    let no_settings = Map::new();
+    let settings = |results: &Value| {
+        results["settings"]
+            .as_object()
+            .cloned()
+            .unwrap_or_else(|| no_settings.clone())
+    };
+    if settings(baseline) != settings(current) {
+        report!(
+            "  WARNING: {} was run with the session settings {} rather than {}",
+            path,
+            baseline["settings"],
+            current["settings"]
+        );
+    }
+
+    let empty = Vec::new();
+    let baseline_phases = baseline["phases"].as_array().unwrap_or(&empty);
+    let mut regressions = 0;
+
+    for phase in current["phases"].as_array().unwrap_or(&empty) {
+        let name = match phase["point"].as_object() {
+            Some(point) => {
+                let values: Vec<String> = point.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                format!("{} [{}]", phase["name"].as_str().unwrap_or(""), values.join(" "))
+            }
+            None => phase["name"].as_str().unwrap_or("").to_string(),
+        };
+        let base = match baseline_phases
+            .iter()
+            .find(|p| p["name"] == phase["name"] && p["point"] == phase["point"])
+        {
+            Some(base) => base,
+            None => {
+                report!("  {}: not in baseline", name);
+                continue;
+            }
+        };
+
+        let throughput = phase["throughput"].as_f64().unwrap_or(0.0);
+        let base_throughput = base["throughput"].as_f64().unwrap_or(0.0);
+        let change = relative_change(throughput, base_throughput);
+        let regressed = change < -threshold;
+        report!(
+            "  {} throughput: {:.1} ops/s baseline: {:.1} ops/s ({:+.1}%){}",
+            name,
+            throughput,
+            base_throughput,
+            change,
+            if regressed { " REGRESSION" } else { "" }
+        );
+        if regressed {
+            regressions += 1;
+        }
+
+        let latencies = match phase["latencies"].as_object() {
+            Some(latencies) => latencies,
+            None => continue,
+        };
+        for (operation, percentiles) in latencies {
+            for &(percentile, _) in PERCENTILES.iter() {
+                let (value, base_value) = match (
+                    percentiles[percentile].as_f64(),
+                    base["latencies"][operation][percentile].as_f64(),
+                ) {
+                    (Some(value), Some(base_value)) => (value, base_value),
+                    _ => continue,
+                };
+                let change = relative_change(value, base_value);
+                let regressed = change > threshold;
+                report!(
+                    "  {} {} {}: {} ns baseline: {} ns ({:+.1}%){}",
+                    name,
+                    operation,
+                    percentile,
+                    value,
+                    base_value,
+                    change,
+                    if regressed { " REGRESSION" } else { "" }
+                );
+                if regressed {
+                    regressions += 1;
+                }
+            }
+        }
+    }
+
+    report!("{} regressions", regressions);
+    regressions
+}
+
+fn relative_change(value: f64, base: f64) -> f64 {
+    if base == 0.0 {
+        0.0
+    } else {
+        (value - base) * 100.0 / base
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(phases: Value) -> Value {
+        json!({"app": "insert-test", "settings": {}, "phases": phases})
+    }
+
+    fn phase(name: &str, throughput: f64, p50: u64) -> Value {
+        json!({
+            "name": name,
+            "throughput": throughput,
+            "latencies": {"Insert": {"p50": p50, "p90": 200, "p99": 300, "p999": 400}},
+        })
+    }
+
+    #[test]
+    fn trial_values_walk_nested_paths() {
+        let trials = vec![
+            json!({"throughput": 10.0, "latencies": {"Insert": {"p50": 100}}}),
+            json!({"throughput": 20.0, "latencies": {"Select": {"p50": 50}}}),
+            json!({"throughput": 30.0, "latencies": {"Insert": {"p50": 300}}}),
+        ];
+        assert_eq!(trial_values(&trials, &["throughput"]), vec![10.0, 20.0, 30.0]);
+        assert_eq!(trial_values(&trials, &["latencies", "Insert", "p50"]), vec![100.0, 300.0]);
+        assert!(trial_values(&trials, &["latencies", "Update", "p50"]).is_empty());
+        assert_eq!(operations(&trials), vec!["Insert".to_string(), "Select".to_string()]);
+    }
+
+    #[test]
+    fn relative_change_in_percent() {
+        assert_eq!(relative_change(110.0, 100.0), 10.0);
+        assert_eq!(relative_change(75.0, 100.0), -25.0);
+        assert_eq!(relative_change(100.0, 100.0), 0.0);
+        // Nothing to compare against
+        assert_eq!(relative_change(5.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn compare_counts_changes_beyond_the_threshold() {
+        let baseline = results(json!([phase("Batched txns", 1000.0, 100)]));
+        assert_eq!(compare(&baseline, &baseline, "base.json", 10.0), 0);
+
+        // 15% fewer ops/s and a p50 15% higher
+        let current = results(json!([phase("Batched txns", 850.0, 115)]));
+        assert_eq!(compare(&current, &baseline, "base.json", 10.0), 2);
+        assert_eq!(compare(&current, &baseline, "base.json", 20.0), 0);
+
+        // Improvements are never regressions
+        let current = results(json!([phase("Batched txns", 2000.0, 50)]));
+        assert_eq!(compare(&current, &baseline, "base.json", 10.0), 0);
+    }
+
+    #[test]
+    fn compare_skips_what_the_baseline_lacks() {
+        let baseline = results(json!([
+            {"name": "Batched txns", "throughput": 1000.0, "latencies": {"Insert": {"p50": 100}}},
+        ]));
+        let current = results(json!([
+            // Not in the baseline at all
+            phase("Separate txns", 1.0, 1_000_000),
+            // In the baseline at another sweep point
+            {
+                "name": "Batched txns",
+                "throughput": 1.0,
+                "latencies": {},
+                "point": {"threads": 4},
+            },
+            // Only the p50 of Insert is in the baseline, and Select isn't
+            {
+                "name": "Batched txns",
+                "throughput": 1000.0,
+                "latencies": {
+                    "Insert": {"p50": 100, "p90": 1_000_000, "p99": 1_000_000, "p999": 1_000_000},
+                    "Select": {"p50": 1_000_000},
+                },
+            },
+        ]));
+        assert_eq!(compare(&current, &baseline, "base.json", 10.0), 0);
+
+        let empty = results(json!([]));
+        assert_eq!(compare(&current, &empty, "base.json", 10.0), 0);
+        assert_eq!(compare(&current, &json!({}), "base.json", 10.0), 0);
+    }
+
+    #[test]
+    fn compare_ignores_a_zero_baseline() {
+        let baseline = results(json!([phase("Batched txns", 0.0, 0)]));
+        let current = results(json!([phase("Batched txns", 1000.0, 1_000_000)]));
+        assert_eq!(compare(&current, &baseline, "base.json", 10.0), 0);
+    }
+}
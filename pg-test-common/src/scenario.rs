@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::process;
+use std::str::FromStr;
+
+use toml;
+
+use durability::{self, Durability};
+use sweep;
+use take_option;
+
+/*
+ * A scenario file describes a whole run in TOML so that it can be kept
+ * alongside its results and repeated exactly, rather than re-typed as
+ * positional arguments. Every setting is optional: anything given on the
+ * command line overrides the file, and anything in neither gets the
+ * utility's default. For example:
+ *
+ *     threads = "1..16*2"
+ *     iterations = 10000
+ *     batch_size = 100
+ *     phases = ["batched"]
+ *     trials = 3
+ *     order = "alternate"
+ *     cleanup = "phase"
+ *     durability = "on,off"
+ *
+ *     [connection]
+ *     url = "postgresql://postgres@localhost:5432/test"
+ *
+ *     [payload]
+ *     name = "Steven"
+ *     size = 999
+ *
+ *     [settings]
+ *     work_mem = "64MB"
+ *     jit = false
+ *
+ * Settings a utility has no use for are ignored by it, so one file can
+ * describe the same shape of run for several utilities.
+ */
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub connection: ConnectionSettings,
+    // The phases to run, by the name of their mode or strategy
+    pub phases: Option<Vec<String>>,
+    pub threads: Option<Values>,
+    // Inserts or writes per thread
+    pub iterations: Option<u32>,
+    pub batch_size: Option<Values>,
+    pub buckets: Option<Values>,
+    pub keys: Option<usize>,
+    pub key_distribution: Option<String>,
+    // Milliseconds between compactions of the bucketed strategies
+    pub compact_interval: Option<u64>,
+    // Milliseconds between samples of the count reader and the wait and lock samplers
+    pub count_interval: Option<u64>,
+    pub wait_sample_interval: Option<u64>,
+    pub lock_sample_interval: Option<u64>,
+    pub trials: Option<u32>,
+    pub order: Option<String>,
+    pub payload: Payload,
+    pub cleanup: Cleanup,
+    // The durabilities to run every phase with, as a comma separated list
+    pub durability: Option<String>,
+    // Session settings for every connection the workers open
+    pub settings: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionSettings {
+    pub url: Option<String>,
+}
+
+// The person rows written by the workers
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Payload {
+    pub name: String,
+    // Bytes of the data column
+    pub size: usize,
+}
+
+impl Default for Payload {
+    fn default() -> Payload {
+        Payload {
+            name: "Steven".to_string(),
+            size: 999,
+        }
+    }
+}
+
+impl Payload {
+    pub fn data(&self) -> String {
+        "a".repeat(self.size)
+    }
+}
+
+/*
+ * When the rows a run wrote are deleted: after every phase so that each
+ * starts from empty tables, once at the end of the run, or never so they can
+ * be inspected afterwards.
+ */
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Cleanup {
+    #[default]
+    Phase,
+    End,
+    Never,
+}
+
+// A count given either as a number or as a list of values and ranges to sweep
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Values {
+    Count(u32),
+    List(String),
+}
+
+impl Values {
+    pub fn parse(&self) -> Result<Vec<u32>, String> {
+        match *self {
+            Values::Count(count) => Ok(vec![count]),
+            Values::List(ref list) => sweep::parse_values(list),
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Scenario {
+        let text = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("unable to read scenario {}: {}", path, e);
+            process::exit(1)
+        });
+        let scenario: Scenario = toml::from_str(&text).unwrap_or_else(|e| {
+            eprintln!("invalid scenario {}: {}", path, e);
+            process::exit(1)
+        });
+
+        // Counts are validated as the command line's are
+        let zero = if scenario.iterations == Some(0) {
+            Some("iterations")
+        } else if scenario.keys == Some(0) {
+            Some("keys")
+        } else {
+            None
+        };
+        if let Some(name) = zero {
+            eprintln!("invalid scenario {}: {} must be at least 1", path, name);
+            process::exit(1);
+        }
+        scenario
+    }
+
+    // Load the scenario named by --scenario FILE, if there is one
+    pub fn from_args(args: &mut Vec<String>) -> Scenario {
+        match take_option(args, "--scenario") {
+            Some(path) => Scenario::load(&path),
+            None => Scenario::default(),
+        }
+    }
+
+    pub fn order<T: FromStr<Err = String>>(&self) -> Option<T> {
+        parse_setting(&self.order)
+    }
+
+    pub fn key_distribution<T: FromStr<Err = String>>(&self) -> Option<T> {
+        parse_setting(&self.key_distribution)
+    }
+
+    pub fn durability(&self) -> Option<Vec<Durability>> {
+        self.durability.as_ref().map(|list| {
+            durability::parse_list(list).unwrap_or_else(|e| {
+                eprintln!("invalid scenario: {}", e);
+                process::exit(1)
+            })
+        })
+    }
+
+    /*
+     * The phases of `available` the scenario selects, in the order they are
+     * given in, or all of them when it doesn't say. `name` gives the name
+     * each phase is selected by.
+     */
+    pub fn select_phases<T: Clone, F: Fn(&T) -> &str>(&self, available: &[T], name: F) -> Vec<T> {
+        let selected = match self.phases {
+            Some(ref selected) => selected,
+            None => return available.to_vec(),
+        };
+
+        selected
+            .iter()
+            .map(|phase| {
+                available
+                    .iter()
+                    .find(|candidate| name(candidate) == phase)
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        let names: Vec<&str> = available.iter().map(&name).collect();
+                        eprintln!(
+                            "invalid scenario: unknown phase {}, expected one of: {}",
+                            phase,
+                            names.join(", ")
+                        );
+                        process::exit(1)
+                    })
+            })
+            .collect()
+    }
+}
+
+fn parse_setting<T: FromStr<Err = String>>(value: &Option<String>) -> Option<T> {
+    value.as_ref().map(|value| {
+        value.parse().unwrap_or_else(|e| {
+            eprintln!("invalid scenario: {}", e);
+            process::exit(1)
+        })
+    })
+}
+
+/*
+ * A positional argument, unless it was left out or given as "-" to keep the
+ * scenario's setting while still overriding the arguments after it.
+ */
+pub fn positional(args: &[String], index: usize) -> Option<&str> {
+    match args.get(index) {
+        Some(arg) if arg != "-" => Some(arg),
+        _ => None,
+    }
+}
@@ -0,0 +1,158 @@
+use std::process;
+
+use clap::ArgMatches;
+use postgres::Connection;
+use serde_json::{Map, Value};
+use tokio::runtime::Runtime;
+use tokio_postgres::Client;
+use toml;
+
+use scenario::Scenario;
+use take_option;
+
+static SET_CONFIG_SQL: &str = "SELECT set_config($1, $2, false)";
+
+/*
+ * Session settings given with `--set name=value`, or in the [settings] table
+ * of a scenario, and set on every connection the workers open, so that
+ * server knobs such as work_mem, jit or plan_cache_mode can be tried without
+ * editing postgresql.conf between runs. A setting given on the command line
+ * overrides the scenario's. They are recorded with the results.
+ */
+#[derive(Clone, Default)]
+pub struct Settings {
+    settings: Vec<(String, String)>,
+}
+
+// A NAME=VALUE setting
+fn parse(setting: &str) -> Result<(String, String), String> {
+    match setting.find('=') {
+        Some(index) if index > 0 => Ok((
+            setting[..index].to_string(),
+            setting[index + 1..].to_string(),
+        )),
+        _ => Err(format!("expected NAME=VALUE, got {}", setting)),
+    }
+}
+
+// Validates a --set setting
+pub fn validate(setting: String) -> Result<(), String> {
+    parse(&setting).map(|_| ())
+}
+
+impl Settings {
+    fn new(scenario: &Scenario, given: Vec<(String, String)>) -> Settings {
+        let mut settings: Vec<(String, String)> = scenario
+            .settings
+            .iter()
+            .map(|(name, value)| {
+                let value = match *value {
+                    toml::Value::String(ref value) => value.clone(),
+                    ref value => value.to_string(),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        for (name, value) in given {
+            settings.retain(|setting| setting.0 != name);
+            settings.push((name, value));
+        }
+        Settings { settings }
+    }
+
+    // Take every --set NAME=VALUE off a positional command line
+    pub fn from_args(args: &mut Vec<String>, scenario: &Scenario) -> Settings {
+        let mut given = Vec::new();
+        while let Some(setting) = take_option(args, "--set") {
+            given.push(parse(&setting).unwrap_or_else(|e| {
+                eprintln!("--set: {}", e);
+                process::exit(1)
+            }));
+        }
+        Settings::new(scenario, given)
+    }
+
+    // The settings of the repeatable "set" argument of cli::args()
+    pub fn from_matches(matches: &ArgMatches, scenario: &Scenario) -> Settings {
+        let given = match matches.values_of("set") {
+            Some(values) => values.map(|setting| parse(setting).unwrap()).collect(),
+            None => Vec::new(),
+        };
+        Settings::new(scenario, given)
+    }
+
+    /*
+     * Try every setting in a transaction that is rolled back, so that an
+     * unknown name or invalid value is reported before anything runs.
+     */
+    pub fn check(&self, conn: &Connection) {
+        if self.settings.is_empty() {
+            return;
+        }
+        let trans = conn.transaction().unwrap();
+        for (name, value) in &self.settings {
+            if let Err(e) = trans.execute("SELECT set_config($1, $2, true)", &[name, value]) {
+                eprintln!("--set {}={}: {}", name, value, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    pub fn apply(&self, conn: &Connection) {
+        for (name, value) in &self.settings {
+            conn.execute(SET_CONFIG_SQL, &[name, value]).unwrap();
+        }
+    }
+
+    // Like apply(), for a connection of the async engine
+    pub fn apply_async(&self, runtime: &Runtime, client: &Client) {
+        for (name, value) in &self.settings {
+            runtime
+                .block_on(client.execute(SET_CONFIG_SQL, &[name, value]))
+                .unwrap();
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let settings: Map<String, Value> = self
+            .settings
+            .iter()
+            .map(|(name, value)| (name.clone(), json!(value)))
+            .collect();
+        Value::Object(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_name_and_value() {
+        assert_eq!(parse("work_mem=64MB"), Ok(("work_mem".to_string(), "64MB".to_string())));
+        assert_eq!(parse("jit=off"), Ok(("jit".to_string(), "off".to_string())));
+        // Only the first = separates the name from the value
+        assert_eq!(parse("search_path=a=b"), Ok(("search_path".to_string(), "a=b".to_string())));
+        assert_eq!(parse("application_name="), Ok(("application_name".to_string(), String::new())));
+    }
+
+    #[test]
+    fn parse_rejects_a_missing_name_or_value() {
+        assert!(parse("work_mem").is_err());
+        assert!(parse("=64MB").is_err());
+        assert!(validate("jit".to_string()).is_err());
+        assert!(validate("jit=on".to_string()).is_ok());
+    }
+
+    #[test]
+    fn command_line_overrides_scenario() {
+        let mut scenario = Scenario::default();
+        scenario.settings.insert("jit".to_string(), toml::Value::Boolean(false));
+        scenario.settings.insert("work_mem".to_string(), toml::Value::String("4MB".to_string()));
+        let mut args = vec!["--set".to_string(), "work_mem=64MB".to_string(), "url".to_string()];
+
+        let settings = Settings::from_args(&mut args, &scenario);
+        assert_eq!(args, vec!["url".to_string()]);
+        assert_eq!(settings.to_json(), json!({"jit": "false", "work_mem": "64MB"}));
+    }
+}
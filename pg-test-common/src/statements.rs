@@ -0,0 +1,131 @@
+use postgres::Connection;
+
+// How many of the most expensive statements to print for each phase
+const TOP_STATEMENTS: usize = 10;
+
+// How much of each statement to show
+const QUERY_SUMMARY_LENGTH: usize = 80;
+
+// The execution and planning times were split apart in PostgreSQL 13
+static STATEMENTS_SQL: &str = "SELECT query, calls, \
+    total_exec_time, mean_exec_time, stddev_exec_time, total_plan_time, mean_plan_time, \
+    rows, shared_blks_hit, shared_blks_read, shared_blks_dirtied, shared_blks_written \
+    FROM pg_stat_statements \
+    WHERE query NOT LIKE '%pg_stat_statements%' \
+    AND dbid IN (SELECT oid FROM pg_database \
+                 WHERE datname = ANY($1) OR (cardinality($1) = 0 AND datname = current_database())) \
+    ORDER BY total_exec_time DESC";
+
+static STATEMENTS_12_SQL: &str = "SELECT query, calls, \
+    total_time, mean_time, stddev_time, 0::float8, 0::float8, \
+    rows, shared_blks_hit, shared_blks_read, shared_blks_dirtied, shared_blks_written \
+    FROM pg_stat_statements \
+    WHERE query NOT LIKE '%pg_stat_statements%' \
+    AND dbid IN (SELECT oid FROM pg_database \
+                 WHERE datname = ANY($1) OR (cardinality($1) = 0 AND datname = current_database())) \
+    ORDER BY total_time DESC";
+
+// Resetting the statements of a single database needs PostgreSQL 12 or later
+static RESET_SQL: &str = "SELECT pg_stat_statements_reset(0, oid, 0) FROM pg_database \
+    WHERE datname = ANY($1) OR (cardinality($1) = 0 AND datname = current_database())";
+
+static RESET_11_SQL: &str = "SELECT pg_stat_statements_reset()";
+
+fn server_version(conn: &Connection) -> i32 {
+    let rows = conn
+        .query("SELECT current_setting('server_version_num')::int", &[])
+        .unwrap();
+    rows.get(0).get(0)
+}
+
+/*
+ * Clear pg_stat_statements of the statements run in `databases`, the
+ * databases the phase's workers use, or in the connection's own database
+ * when there are none, so that what is reported after a phase covers only
+ * that phase. Before PostgreSQL 12 every database's statements are cleared.
+ * The view reports the statements of every database, but only exists where
+ * the extension is installed, so returns false when it isn't installed in
+ * the connection's database (or can't be reset), in which case the phase's
+ * statements shouldn't be reported either.
+ */
+pub fn reset(conn: &Connection, databases: &[String]) -> bool {
+    let rows = conn
+        .query(
+            "SELECT count(*) FROM pg_extension WHERE extname = 'pg_stat_statements'",
+            &[],
+        )
+        .unwrap();
+    let installed: i64 = rows.get(0).get(0);
+    if installed == 0 {
+        return false;
+    }
+
+    let reset = if server_version(conn) >= 120_000 {
+        conn.execute(RESET_SQL, &[&databases])
+    } else {
+        conn.execute(RESET_11_SQL, &[])
+    };
+    match reset {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("pg_stat_statements reset failed: {}", e);
+            false
+        }
+    }
+}
+
+/*
+ * Print totals over every statement run in `databases`, as given to reset,
+ * since the last reset, followed by the statements with the highest total
+ * execution time. Times are in
+ * milliseconds and only include what the server spent, so comparing them
+ * with the client side latencies shows how much is spent elsewhere.
+ */
+pub fn print_top(conn: &Connection, phase: &str, databases: &[String]) {
+    let sql = if server_version(conn) >= 130_000 {
+        STATEMENTS_SQL
+    } else {
+        STATEMENTS_12_SQL
+    };
+
+    let rows = conn.query(sql, &[&databases]).unwrap();
+    let mut calls = 0;
+    let mut exec_time = 0.0;
+    let mut plan_time = 0.0;
+    for row in rows.iter() {
+        calls += row.get::<_, i64>(1);
+        exec_time += row.get::<_, f64>(2);
+        plan_time += row.get::<_, f64>(5);
+    }
+
+    report!(
+        "{} statements ({} distinct, calls: {}, exec time: {:.1} ms, planning time: {:.1} ms):",
+        phase,
+        rows.len(),
+        calls,
+        exec_time,
+        plan_time
+    );
+
+    for row in rows.iter().take(TOP_STATEMENTS) {
+        let query: String = row.get(0);
+        let query: Vec<&str> = query.split_whitespace().collect();
+        let query: String = query.join(" ").chars().take(QUERY_SUMMARY_LENGTH).collect();
+        let calls: i64 = row.get(1);
+        let mean: f64 = row.get(3);
+        let stddev: f64 = row.get(4);
+        let mean_plan: f64 = row.get(6);
+        let rows: i64 = row.get(7);
+        let hit: i64 = row.get(8);
+        let read: i64 = row.get(9);
+        let dirtied: i64 = row.get(10);
+        let written: i64 = row.get(11);
+
+        report!("  {}", query);
+        report!(
+            "    calls: {} mean: {:.3} ms stddev: {:.3} ms mean plan: {:.3} ms rows: {} \
+             shared blks hit: {} read: {} dirtied: {} written: {}",
+            calls, mean, stddev, mean_plan, rows, hit, read, dirtied, written
+        );
+    }
+}
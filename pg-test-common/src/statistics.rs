@@ -0,0 +1,217 @@
+use histogram::Histogram;
+
+// Two sided 95% critical values of Student's t distribution for 1 to 30
+// degrees of freedom. Beyond that the normal distribution is close enough.
+static T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+const Z_95: f64 = 1.96;
+
+/*
+ * The mean of a set of trials, their sample standard deviation and the
+ * half width of the 95% confidence interval of the mean.
+ */
+pub struct Summary {
+    pub n: usize,
+    pub mean: f64,
+    pub stddev: f64,
+    pub ci: f64,
+}
+
+impl Summary {
+    pub fn new(values: &[f64]) -> Summary {
+        let n = values.len();
+        let mean = if n == 0 {
+            0.0
+        } else {
+            values.iter().sum::<f64>() / n as f64
+        };
+        if n < 2 {
+            return Summary {
+                n,
+                mean,
+                stddev: 0.0,
+                ci: 0.0,
+            };
+        }
+
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        let stddev = variance.sqrt();
+        let t = T_95.get(n - 2).cloned().unwrap_or(Z_95);
+
+        Summary {
+            n,
+            mean,
+            stddev,
+            ci: t * stddev / (n as f64).sqrt(),
+        }
+    }
+}
+
+/*
+ * The Mann-Whitney U test of whether the latencies in one histogram tend to
+ * be larger than those in another. `a_slower` is the fraction of all pairs
+ * of samples in which the sample from `a` is the slower one, counting ties
+ * as half, so 0.5 means neither tends to be slower. Samples that share a
+ * histogram bucket are treated as ties, and the p value comes from the
+ * normal approximation with the usual correction for ties, which is sound
+ * for the sample counts a phase produces.
+ */
+pub struct MannWhitney {
+    pub a_slower: f64,
+    pub z: f64,
+    pub p: f64,
+}
+
+impl MannWhitney {
+    pub fn new(a: &Histogram, b: &Histogram) -> Option<MannWhitney> {
+        let n1 = a.entries() as f64;
+        let n2 = b.entries() as f64;
+        if n1 == 0.0 || n2 == 0.0 {
+            return None;
+        }
+
+        // Walk both sets of buckets in order of value, as in a merge
+        let a_buckets = a.buckets();
+        let b_buckets = b.buckets();
+        let (mut i, mut j) = (0, 0);
+        let mut u = 0.0;
+        let mut b_below = 0.0;
+        let mut ties = 0.0;
+        while i < a_buckets.len() || j < b_buckets.len() {
+            let value = match (a_buckets.get(i), b_buckets.get(j)) {
+                (Some(a_bucket), Some(b_bucket)) => a_bucket.0.min(b_bucket.0),
+                (Some(a_bucket), None) => a_bucket.0,
+                (None, Some(b_bucket)) => b_bucket.0,
+                (None, None) => break,
+            };
+            let mut a_count = 0.0;
+            if i < a_buckets.len() && a_buckets[i].0 == value {
+                a_count = a_buckets[i].1 as f64;
+                i += 1;
+            }
+            let mut b_count = 0.0;
+            if j < b_buckets.len() && b_buckets[j].0 == value {
+                b_count = b_buckets[j].1 as f64;
+                j += 1;
+            }
+
+            u += a_count * (b_below + b_count / 2.0);
+            b_below += b_count;
+            let tied = a_count + b_count;
+            ties += tied.powi(3) - tied;
+        }
+
+        let n = n1 + n2;
+        let mean = n1 * n2 / 2.0;
+        let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+        let z = if variance > 0.0 {
+            (u - mean) / variance.sqrt()
+        } else {
+            0.0
+        };
+
+        Some(MannWhitney {
+            a_slower: u / (n1 * n2),
+            z,
+            p: erfc(z.abs() / 2f64.sqrt()),
+        })
+    }
+}
+
+// The complementary error function, to within 1.2e-7 (Numerical Recipes' erfcc)
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let poly = -x * x - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let value = t * poly.exp();
+    if x >= 0.0 {
+        value
+    } else {
+        2.0 - value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(values: &[u64]) -> Histogram {
+        let mut histogram = Histogram::new();
+        for &value in values {
+            histogram.increment(value);
+        }
+        histogram
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() < tolerance, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn summary_of_one_value_has_no_interval() {
+        let summary = Summary::new(&[3.0]);
+        assert_eq!(summary.n, 1);
+        assert_close(summary.mean, 3.0, 1e-12);
+        assert_close(summary.ci, 0.0, 1e-12);
+    }
+
+    #[test]
+    fn summary_uses_t_for_few_trials() {
+        // 1 degree of freedom: t = 12.706 and stddev = sqrt(2)
+        let summary = Summary::new(&[10.0, 12.0]);
+        assert_close(summary.mean, 11.0, 1e-12);
+        assert_close(summary.stddev, 2f64.sqrt(), 1e-12);
+        assert_close(summary.ci, 12.706, 1e-9);
+
+        // 7 degrees of freedom: t = 2.365
+        let summary = Summary::new(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_close(summary.mean, 5.0, 1e-12);
+        assert_close(summary.stddev, 2.138_089_935, 1e-9);
+        assert_close(summary.ci, 1.787_771_957, 1e-9);
+    }
+
+    #[test]
+    fn summary_uses_normal_for_many_trials() {
+        let values: Vec<f64> = (0..40).map(|i| (i % 2) as f64).collect();
+        let summary = Summary::new(&values);
+        assert_close(summary.ci, Z_95 * summary.stddev / 40f64.sqrt(), 1e-12);
+    }
+
+    #[test]
+    fn mann_whitney_without_ties() {
+        let result = MannWhitney::new(&histogram(&[1, 2, 3]), &histogram(&[4, 5, 6])).unwrap();
+        assert_close(result.a_slower, 0.0, 1e-12);
+        assert_close(result.z, -1.963_961_012, 1e-6);
+        assert_close(result.p, 0.049_534_613, 1e-6);
+    }
+
+    #[test]
+    fn mann_whitney_corrects_for_ties() {
+        // U = 3 of 16 pairs, with two groups of three tied samples
+        let result = MannWhitney::new(&histogram(&[1, 2, 2, 3]), &histogram(&[2, 3, 3, 4])).unwrap();
+        assert_close(result.a_slower, 0.1875, 1e-12);
+        assert_close(result.z, -1.517_442_447, 1e-6);
+        assert_close(result.p, 0.129_155_014, 1e-6);
+
+        let reversed = MannWhitney::new(&histogram(&[2, 3, 3, 4]), &histogram(&[1, 2, 2, 3])).unwrap();
+        assert_close(reversed.a_slower, 0.8125, 1e-12);
+        assert_close(reversed.z, 1.517_442_447, 1e-6);
+    }
+
+    #[test]
+    fn mann_whitney_of_identical_samples() {
+        let result = MannWhitney::new(&histogram(&[5, 5]), &histogram(&[5, 5])).unwrap();
+        assert_close(result.a_slower, 0.5, 1e-12);
+        assert_close(result.z, 0.0, 1e-12);
+        assert!(MannWhitney::new(&histogram(&[]), &histogram(&[5])).is_none());
+    }
+}
@@ -0,0 +1,211 @@
+use std::thread;
+use std::time::Duration;
+
+use postgres::Connection;
+
+// Backends flush their pending statistics at most once a second
+const STATS_SETTLE_TIME: Duration = Duration::from_millis(1100);
+
+/*
+ * Each group of counters is read with a single query that returns one row of
+ * bigint columns. The column names are used when reporting deltas.
+ */
+static DATABASE_SQL: &str = "SELECT \
+    sum(xact_commit)::bigint AS xact_commit, \
+    sum(xact_rollback)::bigint AS xact_rollback, \
+    sum(blks_read)::bigint AS blks_read, \
+    sum(blks_hit)::bigint AS blks_hit, \
+    sum(tup_returned)::bigint AS tup_returned, \
+    sum(tup_fetched)::bigint AS tup_fetched, \
+    sum(tup_inserted)::bigint AS tup_inserted, \
+    sum(tup_updated)::bigint AS tup_updated, \
+    sum(tup_deleted)::bigint AS tup_deleted, \
+    sum(conflicts)::bigint AS conflicts, \
+    sum(temp_bytes)::bigint AS temp_bytes, \
+    sum(deadlocks)::bigint AS deadlocks \
+    FROM pg_stat_database";
+
+static TABLES_SQL: &str = "SELECT \
+    coalesce(sum(seq_scan), 0)::bigint AS seq_scan, \
+    coalesce(sum(idx_scan), 0)::bigint AS idx_scan, \
+    coalesce(sum(n_tup_ins), 0)::bigint AS n_tup_ins, \
+    coalesce(sum(n_tup_upd), 0)::bigint AS n_tup_upd, \
+    coalesce(sum(n_tup_hot_upd), 0)::bigint AS n_tup_hot_upd, \
+    coalesce(sum(n_tup_del), 0)::bigint AS n_tup_del, \
+    coalesce(sum(n_dead_tup), 0)::bigint AS n_dead_tup \
+    FROM pg_stat_user_tables";
+
+static INDEXES_SQL: &str = "SELECT \
+    coalesce(sum(idx_scan), 0)::bigint AS idx_scan, \
+    coalesce(sum(idx_tup_read), 0)::bigint AS idx_tup_read, \
+    coalesce(sum(idx_tup_fetch), 0)::bigint AS idx_tup_fetch \
+    FROM pg_stat_user_indexes";
+
+static BGWRITER_SQL: &str = "SELECT \
+    buffers_clean, maxwritten_clean, buffers_alloc \
+    FROM pg_stat_bgwriter";
+
+// The checkpointer counters moved out of pg_stat_bgwriter in PostgreSQL 17
+static CHECKPOINTER_SQL: &str = "SELECT \
+    checkpoints_timed, checkpoints_req, buffers_checkpoint \
+    FROM pg_stat_bgwriter";
+
+static CHECKPOINTER_17_SQL: &str = "SELECT \
+    num_timed AS checkpoints_timed, num_requested AS checkpoints_req, \
+    buffers_written AS buffers_checkpoint \
+    FROM pg_stat_checkpointer";
+
+pub struct StatGroup {
+    pub name: &'static str,
+    pub counters: Vec<(String, i64)>,
+}
+
+impl StatGroup {
+    fn read(conn: &Connection, name: &'static str, sql: &str) -> StatGroup {
+        let rows = conn.query(sql, &[]).unwrap();
+        let row = rows.get(0);
+        let counters = row
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let value: Option<i64> = row.get(i);
+                (column.name().to_string(), value.unwrap_or(0))
+            })
+            .collect();
+
+        StatGroup { name, counters }
+    }
+
+    pub fn get(&self, counter: &str) -> i64 {
+        self.counters
+            .iter()
+            .find(|&(name, _)| name == counter)
+            .map(|&(_, value)| value)
+            .unwrap_or(0)
+    }
+}
+
+/*
+ * A point in time view of the server's cumulative statistics. Database wide
+ * counters are summed over every database, while the table and index
+ * counters only cover the database of the connection used to take the
+ * snapshot.
+ *
+ * Backends only flush their statistics periodically (and when they exit), so
+ * counters can trail the actual activity by up to a second. Use take_settled
+ * at the end of a phase to give the workers' statistics time to arrive.
+ */
+pub struct Snapshot {
+    pub wal_lsn: u64,
+    pub groups: Vec<StatGroup>,
+}
+
+impl Snapshot {
+    pub fn take(conn: &Connection) -> Snapshot {
+        // Make sure we don't see statistics cached earlier in this session
+        conn.execute("SELECT pg_stat_clear_snapshot()", &[]).unwrap();
+
+        let rows = conn
+            .query(
+                "SELECT pg_current_wal_lsn()::text, current_setting('server_version_num')::int",
+                &[],
+            )
+            .unwrap();
+        let row = rows.get(0);
+        let lsn: String = row.get(0);
+        let server_version: i32 = row.get(1);
+
+        let checkpointer_sql = if server_version >= 170_000 {
+            CHECKPOINTER_17_SQL
+        } else {
+            CHECKPOINTER_SQL
+        };
+
+        Snapshot {
+            wal_lsn: parse_lsn(&lsn),
+            groups: vec![
+                StatGroup::read(conn, "pg_stat_database", DATABASE_SQL),
+                StatGroup::read(conn, "pg_stat_user_tables", TABLES_SQL),
+                StatGroup::read(conn, "pg_stat_user_indexes", INDEXES_SQL),
+                StatGroup::read(conn, "pg_stat_bgwriter", BGWRITER_SQL),
+                StatGroup::read(conn, "checkpointer", checkpointer_sql),
+            ],
+        }
+    }
+
+    pub fn take_settled(conn: &Connection) -> Snapshot {
+        thread::sleep(STATS_SETTLE_TIME);
+        Snapshot::take(conn)
+    }
+
+    fn group(&self, name: &str) -> &StatGroup {
+        self.groups.iter().find(|g| g.name == name).unwrap()
+    }
+}
+
+// An LSN is printed as two 32 bit hex numbers separated by a slash
+fn parse_lsn(lsn: &str) -> u64 {
+    let mut parts = lsn.splitn(2, '/');
+    let high = u64::from_str_radix(parts.next().unwrap_or("0"), 16).unwrap_or(0);
+    let low = u64::from_str_radix(parts.next().unwrap_or("0"), 16).unwrap_or(0);
+    (high << 32) | low
+}
+
+pub fn print_delta(phase: &str, before: &Snapshot, after: &Snapshot) {
+    let wal_bytes = after.wal_lsn.saturating_sub(before.wal_lsn);
+    let delta = |group: &str, counter: &str| {
+        after.group(group).get(counter) - before.group(group).get(counter)
+    };
+
+    let commits = delta("pg_stat_database", "xact_commit");
+    let inserted = delta("pg_stat_database", "tup_inserted");
+    let hit = delta("pg_stat_database", "blks_hit");
+    let read = delta("pg_stat_database", "blks_read");
+
+    report!("{} server statistics:", phase);
+    report!(
+        "  WAL: {} bytes ({:.1} bytes/inserted tuple, {:.1} bytes/commit)",
+        wal_bytes,
+        per(wal_bytes as i64, inserted),
+        per(wal_bytes as i64, commits)
+    );
+    report!(
+        "  Buffers: hit: {} read: {} hit ratio: {:.2}%",
+        hit,
+        read,
+        per(hit * 100, hit + read)
+    );
+
+    for (group_before, group_after) in before.groups.iter().zip(&after.groups) {
+        let deltas: Vec<String> = group_before
+            .counters
+            .iter()
+            .zip(&group_after.counters)
+            .map(|(&(ref name, b), &(_, a))| format!("{}: {}", name, a - b))
+            .collect();
+        report!("  {}: {}", group_before.name, deltas.join(" "));
+    }
+}
+
+fn per(value: i64, count: i64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        value as f64 / count as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lsn() {
+        assert_eq!(parse_lsn("0/0"), 0);
+        assert_eq!(parse_lsn("0/16B374D8"), 0x16B3_74D8);
+        assert_eq!(parse_lsn("1/16B374D8"), 0x1_16B3_74D8);
+        assert_eq!(parse_lsn("FFFFFFFF/FFFFFFFF"), u64::MAX);
+        assert_eq!(parse_lsn("1/0") - parse_lsn("0/FFFFFFFF"), 1);
+    }
+}
@@ -0,0 +1,169 @@
+/*
+ * Parameter sweeps. Any parameter that takes a count can be given a list of
+ * values instead of a single one, and the utility then runs once for every
+ * combination of the values (a point), cleaning up between points. A list is
+ * a comma separated mix of single values and ranges:
+ *
+ *     8            just 8
+ *     1..4         1, 2, 3 and 4
+ *     0..1000+250  0, 250, 500, 750 and 1000
+ *     1..64*2      1, 2, 4, 8, 16, 32 and 64
+ *
+ * so "1,10..100+10,1000" runs 1, every multiple of 10 up to 100 and 1000.
+ */
+
+use std::str::FromStr;
+
+pub fn parse_values(s: &str) -> Result<Vec<u32>, String> {
+    let mut values = Vec::new();
+    for part in s.split(',') {
+        values.extend(parse_range(part)?);
+    }
+    Ok(values)
+}
+
+fn parse_range(s: &str) -> Result<Vec<u32>, String> {
+    let invalid = || format!("invalid value or range: {}", s);
+    let number = |n: &str| u32::from_str(n).map_err(|_| invalid());
+
+    let (start, rest) = match s.find("..") {
+        Some(index) => (&s[..index], &s[index + 2..]),
+        None => return Ok(vec![number(s)?]),
+    };
+    let start = number(start)?;
+    let (end, step) = match rest.find(['+', '*']) {
+        Some(index) => {
+            let step = number(&rest[index + 1..])?;
+            (number(&rest[..index])?, Some((&rest[index..index + 1], step)))
+        }
+        None => (number(rest)?, None),
+    };
+    if end < start {
+        return Err(invalid());
+    }
+
+    let mut values = Vec::new();
+    let mut value = start;
+    while value <= end {
+        values.push(value);
+        let next = match step {
+            None => value.checked_add(1),
+            Some(("+", n)) if n > 0 => value.checked_add(n),
+            Some(("*", n)) if n > 1 && value > 0 => value.checked_mul(n),
+            _ => return Err(invalid()),
+        };
+        value = match next {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Ok(values)
+}
+
+// One combination of the swept parameters' values
+#[derive(Clone)]
+pub struct Point {
+    values: Vec<(&'static str, u32, bool)>,
+}
+
+impl Point {
+    pub fn get(&self, name: &str) -> u32 {
+        self.values
+            .iter()
+            .find(|v| v.0 == name)
+            .map(|v| v.1)
+            .unwrap_or_else(|| panic!("{} is not a sweep parameter", name))
+    }
+
+    // The values of the parameters that take more than one value
+    pub fn swept(&self) -> Vec<(&'static str, u32)> {
+        self.values
+            .iter()
+            .filter(|v| v.2)
+            .map(|v| (v.0, v.1))
+            .collect()
+    }
+
+    pub fn describe(&self) -> String {
+        self.swept()
+            .iter()
+            .map(|&(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+#[derive(Default)]
+pub struct Sweep {
+    parameters: Vec<(&'static str, Vec<u32>)>,
+}
+
+impl Sweep {
+    pub fn new() -> Sweep {
+        Sweep {
+            parameters: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &'static str, values: Vec<u32>) {
+        self.parameters.push((name, values));
+    }
+
+    pub fn is_sweep(&self) -> bool {
+        self.parameters.iter().any(|p| p.1.len() > 1)
+    }
+
+    // The cartesian product of every parameter's values
+    pub fn points(&self) -> Vec<Point> {
+        let mut points = vec![Point { values: Vec::new() }];
+        for &(name, ref values) in &self.parameters {
+            let swept = values.len() > 1;
+            points = points
+                .iter()
+                .flat_map(|point| {
+                    values.iter().map(move |&value| {
+                        let mut point = point.clone();
+                        point.values.push((name, value, swept));
+                        point
+                    })
+                })
+                .collect();
+        }
+        points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_values_and_ranges() {
+        assert_eq!(parse_values("8"), Ok(vec![8]));
+        assert_eq!(parse_values("1..4"), Ok(vec![1, 2, 3, 4]));
+        assert_eq!(parse_values("0..1000+250"), Ok(vec![0, 250, 500, 750, 1000]));
+        assert_eq!(parse_values("4..16*2"), Ok(vec![4, 8, 16]));
+        assert_eq!(parse_values("4..20*2"), Ok(vec![4, 8, 16]));
+    }
+
+    #[test]
+    fn lists_of_values_and_ranges() {
+        assert_eq!(
+            parse_values("1,10..100+10,1000"),
+            Ok(vec![1, 10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 1000])
+        );
+    }
+
+    #[test]
+    fn ranges_stop_before_overflowing() {
+        assert_eq!(parse_values("4294967294..4294967295"), Ok(vec![4294967294, 4294967295]));
+        assert_eq!(parse_values("2147483648..4294967295*2"), Ok(vec![2147483648]));
+    }
+
+    #[test]
+    fn invalid_values_and_ranges() {
+        for s in &["", "x", "-1", "1..", "..4", "4..1", "1..4+0", "1..4*1", "0..4*2", "1..4-1", "1,,2"] {
+            assert!(parse_values(s).is_err(), "{} was accepted", s);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::process;
+
+use postgres::Connection;
+
+/*
+ * The work a phase actually did, counted by its threads as they go rather
+ * than derived from the counts it was configured with, so that a duration can
+ * be read against the work that took it. Failed transactions are counted on
+ * their own and their rows aren't counted at all.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Work {
+    pub threads: u64,
+    pub transactions: u64,
+    pub failed: u64,
+    pub rows: u64,
+}
+
+impl Work {
+    // The work of one thread, before it has done any
+    pub fn thread() -> Work {
+        Work {
+            threads: 1,
+            ..Work::default()
+        }
+    }
+
+    // A transaction that committed, having written `rows`
+    pub fn commit(&mut self, rows: u64) {
+        self.transactions += 1;
+        self.rows += rows;
+    }
+
+    pub fn fail(&mut self) {
+        self.failed += 1;
+    }
+
+    // Count an operation that returned None because it failed
+    pub fn count<T>(&mut self, rows: u64, outcome: &Option<T>) {
+        match *outcome {
+            Some(_) => self.commit(rows),
+            None => self.fail(),
+        }
+    }
+
+    pub fn add(&mut self, other: &Work) {
+        self.threads += other.threads;
+        self.transactions += other.transactions;
+        self.failed += other.failed;
+        self.rows += other.rows;
+    }
+
+    pub fn print(&self, phase: &str) {
+        report!(
+            "{} work: threads: {} transactions: {} failed: {} rows: {}",
+            phase, self.threads, self.transactions, self.failed, self.rows
+        );
+    }
+}
+
+pub fn count_rows(conn: &Connection, table: &str) -> u64 {
+    let rows = conn.query(&format!("SELECT count(*) FROM {}", table), &[]).unwrap();
+    rows.get(0).get::<_, i64>(0) as u64
+}
+
+/*
+ * Check that the tables a phase wrote to gained exactly the rows its
+ * transactions committed, given the rows counted in them before and after
+ * the phase. A mismatch means the work reported isn't the work that was done.
+ */
+pub fn verify_rows(phase: &str, tables: &str, before: u64, after: u64, work: &Work) {
+    let added = after.saturating_sub(before);
+    if after < before || added != work.rows {
+        eprintln!(
+            "{} row count mismatch: count(*) of {} went from {} to {} but {} rows were committed",
+            phase, tables, before, after, work.rows
+        );
+        process::exit(1);
+    }
+
+    report!("{} rows verified: {} rows added to {}", phase, added, tables);
+}
@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate clap;
+extern crate hierarchy_test;
+extern crate insert_test;
+extern crate update_contention_test;
+
+use clap::{App, AppSettings};
+
+/*
+ * Every utility as a subcommand of one binary, so that they share how the
+ * connection, threads, iterations and output are given:
+ *
+ *     pg-test insert --url URL --threads 1..16*2 --batch-size 100
+ *     pg-test counter --url URL --strategies bucketed,ledger --buckets 10
+ *     pg-test hierarchy --url URL --mode schema --schema-count 1000
+ *
+ * Results and metrics are still labelled with the utility that ran.
+ */
+fn main() {
+    let matches = App::new("pg-test")
+        .about("Tests of PostgreSQL performance under different workloads")
+        .version(crate_version!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(insert_test::subcommand("insert"))
+        .subcommand(update_contention_test::subcommand("counter"))
+        .subcommand(hierarchy_test::subcommand("hierarchy"))
+        .get_matches();
+
+    match matches.subcommand() {
+        ("insert", Some(m)) => insert_test::run(insert_test::Config::from_matches(m)),
+        ("counter", Some(m)) => update_contention_test::run(update_contention_test::Config::from_matches(m)),
+        ("hierarchy", Some(m)) => hierarchy_test::run(hierarchy_test::Config::from_matches(m)),
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::process;
+
+use pg_test_common::histogram::Histogram;
+use pg_test_common;
+use postgres::Connection;
+
+use keys::KeySpace;
+
+pub static APP: &str = "update-contention-test";
+
+// Only the first few mismatched keys are printed to keep the output readable
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+// The tables a phase writes to
+pub static TABLES: [&str; 3] = ["person", "person_count", "person_count_delta"];
+
+pub fn connect(url: &str) -> Connection {
+    pg_test_common::connect(url, APP)
+}
+
+pub fn print_results(read: &Histogram, write: &Histogram) {
+    print_percentiles("Read", read);
+    print_percentiles("Write", write);
+}
+
+pub fn print_percentiles(label: &str, histogram: &Histogram) {
+    if histogram.entries() == 0 {
+        report!("{} Latency Percentiles: no samples", label);
+        return;
+    }
+
+    report!(
+        "{} Latency Percentiles: p50: {} ns p90: {} ns p99: {} ns p999: {}",
+        label,
+        histogram.percentile(50.0),
+        histogram.percentile(90.0),
+        histogram.percentile(99.0),
+        histogram.percentile(99.9),
+    );
+}
+
+pub fn delete_tables(conn: &Connection) {
+    let trans = conn.transaction().unwrap();
+
+    trans.execute("DELETE FROM person;", &[]).unwrap();
+    trans.execute("DELETE FROM person_count;", &[]).unwrap();
+    trans.execute("DELETE FROM person_count_delta;", &[]).unwrap();
+
+    trans.commit().unwrap();
+}
+
+/*
+ * The tally for a name is whatever has been folded into person_count plus
+ * any deltas still waiting in the ledger to be rolled up. Every key must
+ * match the number of increments committed for it.
+ */
+pub fn verify_tally(conn: &Connection, phase: &str, keys: &KeySpace, key_writes: &[u64]) {
+    let rows = conn
+        .query(
+            "SELECT name, sum(count)::bigint FROM \
+             (SELECT name, count FROM person_count \
+              UNION ALL SELECT name, delta FROM person_count_delta) AS tallies \
+             GROUP BY name",
+            &[],
+        )
+        .unwrap();
+    let tallies: HashMap<String, i64> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    let mut mismatches = 0;
+    for (name, committed_writes) in keys.names().iter().zip(key_writes) {
+        let tally = *tallies.get(name).unwrap_or(&0);
+        if tally as u64 != *committed_writes {
+            if mismatches < MAX_REPORTED_MISMATCHES {
+                eprintln!(
+                    "{} tally mismatch for {}: sum(count) is {} but {} writes were committed",
+                    phase, name, tally, committed_writes
+                );
+            }
+            mismatches += 1;
+        }
+    }
+
+    if mismatches > 0 {
+        eprintln!("{} tally mismatch for {} of {} keys", phase, mismatches, keys.len());
+        process::exit(1);
+    }
+
+    report!(
+        "{} tally verified: {} increments across {} keys",
+        phase,
+        key_writes.iter().sum::<u64>(),
+        keys.len()
+    );
+}
@@ -0,0 +1,59 @@
+extern crate postgres;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use pg_test_common::durability::Durability;
+use pg_test_common::histogram::Histogram;
+use pg_test_common::metrics;
+use postgres::Connection;
+
+use common;
+use types::Workload;
+
+/*
+ * Periodically fold every bucket of every name back into bucket 0 while the
+ * writers run, with their session settings and committing as durably as they
+ * do. The compaction latency of each pass is returned once `stop` is set.
+ */
+pub fn spawn(
+    workload: Arc<Workload>,
+    durability: Option<Durability>,
+    interval: Duration,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<Histogram> {
+    thread::spawn(move || {
+        let mut histogram = Histogram::new();
+        let conn = common::connect(&workload.url);
+        workload.settings.apply(&conn);
+        if let Some(durability) = durability {
+            durability.apply(&conn);
+        }
+
+        while !stop.load(Ordering::SeqCst) {
+            thread::sleep(interval);
+
+            metrics::timed("Compaction", &mut histogram, || compact_buckets(&conn));
+        }
+
+        histogram
+    })
+}
+
+/*
+ * Deleting the non-zero buckets and adding their counts to bucket 0 happen in
+ * one statement so that readers summing the buckets never see a tally change
+ * because of a compaction. Writers that race with the delete simply recreate
+ * their bucket.
+ */
+pub fn compact_buckets(conn: &Connection) -> postgres::Result<u64> {
+    conn.execute(
+        "WITH moved AS (DELETE FROM person_count WHERE bucket <> 0 RETURNING name, count) \
+         INSERT INTO person_count (name, bucket, count) \
+         SELECT name, 0, sum(count) FROM moved GROUP BY name \
+         ON CONFLICT (name, bucket) DO UPDATE SET count = person_count.count + excluded.count",
+        &[],
+    )
+}
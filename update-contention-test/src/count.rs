@@ -0,0 +1,179 @@
+extern crate postgres;
+extern crate rand;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use pg_test_common::histogram::Histogram;
+use pg_test_common::metrics;
+use postgres::transaction::{Config, IsolationLevel, Transaction};
+use postgres::Connection;
+
+use common;
+use strategy::Strategy;
+use types::Workload;
+
+// person_name_idx lets this be answered with an index only scan
+pub static COUNT_STAR_SQL: &str = "SELECT count(*) FROM person WHERE name = $1";
+
+/*
+ * The different ways of answering "how many persons named X" that are
+ * compared while the writers run.
+ */
+#[derive(Clone, Copy)]
+pub enum CountMethod {
+    CountStar,
+    Reltuples,
+    Counter,
+}
+
+pub static ALL: [CountMethod; 3] = [CountMethod::CountStar, CountMethod::Reltuples, CountMethod::Counter];
+
+impl CountMethod {
+    pub fn name(self) -> &'static str {
+        match self {
+            CountMethod::CountStar => "count(*)",
+            CountMethod::Reltuples => "reltuples estimate",
+            CountMethod::Counter => "person_count",
+        }
+    }
+
+    fn count(self, trans: &Transaction, strategy: Strategy, name: &str) -> postgres::Result<i64> {
+        let sql = match self {
+            CountMethod::CountStar => COUNT_STAR_SQL.to_string(),
+            CountMethod::Reltuples => RELTUPLES_SQL.to_string(),
+            CountMethod::Counter => format!("SELECT coalesce(({}), 0)::bigint", strategy.read_sql()),
+        };
+
+        let rows = trans.query(sql.as_str(), &[&name])?;
+        Ok(rows.get(0).get(0))
+    }
+}
+
+/*
+ * The person table's estimated row count scaled by the selectivity the
+ * planner would give `name = $1`: the name's frequency when it is one of the
+ * most common values, and otherwise the frequency left over by those and the
+ * nulls, shared evenly by the remaining distinct values. A negative
+ * n_distinct is a fraction of the rows. Without statistics the planner's
+ * default equality selectivity of 0.005 applies.
+ */
+static RELTUPLES_SQL: &str = "SELECT (greatest(c.reltuples, 0) * coalesce( \
+                                (SELECT f.freq FROM \
+                                 unnest(s.most_common_vals::text::text[], s.most_common_freqs) \
+                                 AS f(val, freq) WHERE f.val = $1), \
+                                (1 - coalesce((SELECT sum(freq) FROM unnest(s.most_common_freqs) AS freq), 0) \
+                                 - s.null_frac) \
+                                / greatest(CASE WHEN s.n_distinct < 0 \
+                                                THEN -s.n_distinct * greatest(c.reltuples, 0) \
+                                                ELSE s.n_distinct END \
+                                           - coalesce(array_length(s.most_common_freqs, 1), 0), 1), \
+                                0.005))::bigint \
+                              FROM pg_class c \
+                              JOIN pg_namespace n ON n.oid = c.relnamespace \
+                              LEFT JOIN pg_stats s ON s.schemaname = n.nspname \
+                               AND s.tablename = c.relname AND s.attname = 'name' \
+                              WHERE c.relname = 'person' AND n.nspname = current_schema()";
+
+pub struct MethodResults {
+    pub histogram: Histogram,
+    pub total_error: u64,
+    pub max_error: u64,
+    pub total_relative_error: f64,
+}
+
+/*
+ * Periodically answer the count for a key with every method until `stop` is
+ * set. All methods run in the same repeatable read snapshot so that the
+ * exact count(*) is the true count the others are compared against.
+ */
+pub fn spawn(
+    strategy: Strategy,
+    workload: Arc<Workload>,
+    interval: Duration,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<Vec<MethodResults>> {
+    thread::spawn(move || {
+        let mut results: Vec<MethodResults> = ALL
+            .iter()
+            .map(|_| MethodResults {
+                histogram: Histogram::new(),
+                total_error: 0,
+                max_error: 0,
+                total_relative_error: 0.0,
+            })
+            .collect();
+        let mut rng = rand::thread_rng();
+        let conn = common::connect(&workload.url);
+        workload.settings.apply(&conn);
+        let mut config = Config::new();
+        config.isolation_level(IsolationLevel::RepeatableRead);
+
+        while !stop.load(Ordering::SeqCst) {
+            thread::sleep(interval);
+
+            let name = workload.keys.name(workload.keys.sample(&mut rng));
+            let (counts, durations) = match count_all(&conn, &config, strategy, name) {
+                Ok(sample) => sample,
+                // A sample that failed is left out, as the writers do with theirs
+                Err(e) => {
+                    metrics::error("Count", &e);
+                    continue;
+                }
+            };
+
+            let true_count = counts[CountMethod::CountStar as usize];
+            let samples = counts.iter().zip(durations).zip(results.iter_mut());
+            for ((count, duration), method_results) in samples {
+                let nanos = duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64;
+                method_results.histogram.increment(nanos);
+
+                let error = (count - true_count).unsigned_abs();
+                method_results.total_error += error;
+                method_results.max_error = method_results.max_error.max(error);
+                if true_count > 0 {
+                    method_results.total_relative_error += error as f64 / true_count as f64;
+                }
+            }
+        }
+
+        results
+    })
+}
+
+// The count of every method in one snapshot, and how long each took
+fn count_all(
+    conn: &Connection,
+    config: &Config,
+    strategy: Strategy,
+    name: &str,
+) -> postgres::Result<(Vec<i64>, Vec<Duration>)> {
+    let trans = conn.transaction_with(config)?;
+    let mut counts = Vec::new();
+    let mut durations = Vec::new();
+    for method in ALL.iter() {
+        let start = Instant::now();
+        counts.push(method.count(&trans, strategy, name)?);
+        durations.push(start.elapsed());
+    }
+    trans.commit()?;
+    Ok((counts, durations))
+}
+
+pub fn print_results(results: &[MethodResults]) {
+    for (method, method_results) in ALL.iter().zip(results) {
+        let samples = method_results.histogram.entries();
+        common::print_percentiles(method.name(), &method_results.histogram);
+        if samples > 0 {
+            report!(
+                "{} Error: mean: {:.1} max: {} mean relative: {:.2}%",
+                method.name(),
+                method_results.total_error as f64 / samples as f64,
+                method_results.max_error,
+                method_results.total_relative_error / samples as f64 * 100.0,
+            );
+        }
+    }
+}
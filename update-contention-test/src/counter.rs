@@ -0,0 +1,204 @@
+extern crate postgres;
+extern crate rand;
+
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use pg_test_common::durability::Durability;
+use pg_test_common::histogram::Histogram;
+use pg_test_common::metrics;
+use pg_test_common::replica::{self, Lag};
+use pg_test_common::work::Work;
+use rand::distributions::{Distribution, Uniform};
+
+use common;
+use keys::KEY_CLASSES;
+use strategy::{self, Strategy};
+use types::{HistogramPair, Person, ThreadResults, Workload};
+
+// How many ledger deltas each writer appends between rollups
+const LEDGER_ROLLUP_WRITES: i32 = 100;
+
+/*
+ * Run a phase of the given strategy and return the work its writers did and
+ * the number of committed writes for each key, along with the read and write
+ * latencies across all keys and the lag of the replica if reads went to one.
+ * A write is only counted once its transaction has committed, and failed
+ * reads and writes are counted as errors by the metrics instead. The writers
+ * set their sessions to the durability, if any. With a replica, every write
+ * that commits is handed to a checker that looks for it there.
+ */
+pub fn run_threads(
+    strategy: Strategy,
+    durability: Option<Durability>,
+    workload: Arc<Workload>,
+) -> (Work, Vec<u64>, HistogramPair, Lag) {
+    let keys = &workload.keys;
+    let (commits, checker) = match workload.replica_url {
+        Some(ref url) => {
+            let replica = replica::connect(url, common::APP);
+            workload.settings.apply(&replica);
+            let (commits, checker) = replica::spawn_checker(replica);
+            (Some(commits), Some(checker))
+        }
+        None => (None, None),
+    };
+    let mut handles = Vec::new();
+    for thread_id in 0..workload.thread_count {
+        let workload_clone = Arc::clone(&workload);
+        let commits = commits.clone();
+        let h = thread::spawn(move || {
+            counter_updates(strategy, durability, thread_id, workload_clone, commits)
+        });
+        handles.push(h);
+    }
+    // The checker finishes once every writer has dropped its sender
+    drop(commits);
+
+    let mut class_histograms: Vec<HistogramPair> = KEY_CLASSES
+        .iter()
+        .map(|_| (Histogram::new(), Histogram::new()))
+        .collect();
+    let mut key_writes = vec![0; keys.len()];
+    let mut work = Work::default();
+
+    for handle in handles {
+        match handle.join() {
+            Ok(results) => {
+                work.add(&results.work);
+                for (merged, thread_hists) in class_histograms.iter_mut().zip(&results.histograms) {
+                    merged.0.merge(&thread_hists.0);
+                    merged.1.merge(&thread_hists.1);
+                }
+                for (merged, writes) in key_writes.iter_mut().zip(&results.key_writes) {
+                    *merged += writes;
+                }
+            }
+            Err(_) => report!("{} update thread panicked", strategy.name()),
+        }
+    }
+
+    let mut read_histogram = Histogram::new();
+    let mut write_histogram = Histogram::new();
+    for (read, write) in &class_histograms {
+        read_histogram.merge(read);
+        write_histogram.merge(write);
+    }
+
+    let lag = match checker.map(|checker| checker.join()) {
+        Some(Ok(lag)) => lag,
+        Some(Err(_)) => {
+            report!("replica checker thread panicked");
+            Lag::default()
+        }
+        None => Lag::default(),
+    };
+
+    common::print_results(&read_histogram, &write_histogram);
+
+    // A per class breakdown is only interesting when there is more than one
+    if keys.len() > 1 {
+        for class in KEY_CLASSES.iter() {
+            let (ref read, ref write) = class_histograms[class.index()];
+            if write.entries() == 0 && read.entries() == 0 {
+                continue;
+            }
+            report!(
+                "{} keys ({} keys, {} writes):",
+                class.name(),
+                keys.class_size(*class),
+                write.entries()
+            );
+            common::print_results(read, write);
+        }
+    }
+
+    (work, key_writes, (read_histogram, write_histogram), lag)
+}
+
+fn counter_updates(
+    strategy: Strategy,
+    durability: Option<Durability>,
+    thread_id: i32,
+    workload: Arc<Workload>,
+    commits: Option<Sender<(i64, Instant)>>,
+) -> ThreadResults {
+    let keys = &workload.keys;
+    let mut histograms: Vec<HistogramPair> = KEY_CLASSES
+        .iter()
+        .map(|_| (Histogram::new(), Histogram::new()))
+        .collect();
+    let mut key_writes = vec![0; keys.len()];
+    let mut work = Work::thread();
+    let bucket_distribution = Uniform::from(0..workload.bucket_count);
+    let mut rng = rand::thread_rng();
+    let conn = common::connect(&workload.url);
+    workload.settings.apply(&conn);
+    if let Some(durability) = durability {
+        durability.apply(&conn);
+    }
+    let replica = workload.replica_url.as_ref().map(|url| {
+        let replica = replica::connect(url, common::APP);
+        workload.settings.apply(&replica);
+        replica
+    });
+
+    for number in 1..=workload.thread_writes {
+        let key = keys.sample(&mut rng);
+        let (ref mut read_histogram, ref mut write_histogram) =
+            histograms[keys.class(key).index()];
+        let p = Person::new(keys.name(key), &workload.data);
+
+        let read = metrics::timed("Read", read_histogram, || {
+            let read_trans = replica.as_ref().unwrap_or(&conn).transaction()?;
+            read_trans.execute(strategy.read_sql(), &[&p.name])?;
+            read_trans.commit()
+        });
+        work.count(0, &read);
+
+        let bucket = strategy.bucket(
+            thread_id,
+            bucket_distribution.sample(&mut rng),
+            workload.bucket_count,
+        );
+        let mut committed = None;
+        let mut xid = None;
+        metrics::timed("Write", write_histogram, || {
+            let write_trans = conn.transaction()?;
+            strategy.write(&write_trans, &p, bucket)?;
+            // The replica is checked for this write's own transaction
+            if commits.is_some() {
+                xid = Some(write_trans.query("SELECT txid_current()", &[])?.get(0).get::<_, i64>(0));
+            }
+            write_trans.commit()?;
+            committed = Some(Instant::now());
+
+            // The cost of a rollup is charged to the write that triggered it
+            if let Strategy::Ledger = strategy {
+                if number % LEDGER_ROLLUP_WRITES == 0 {
+                    strategy::rollup_ledger(&conn, &p.name)?;
+                }
+            }
+            Ok(())
+        });
+        match committed {
+            Some(committed) => {
+                key_writes[key] += 1;
+                work.commit(1);
+                if let (Some(ref commits), Some(xid)) = (&commits, xid) {
+                    // The checker only goes away if it panicked, which run_threads reports
+                    let _ = commits.send((xid, committed));
+                }
+            }
+            None => work.fail(),
+        }
+    }
+
+    ThreadResults {
+        work,
+        histograms,
+        key_writes,
+    }
+}
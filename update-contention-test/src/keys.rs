@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+use rand::Rng;
+
+/*
+ * How popular each counter key is. With a Zipfian distribution the key of
+ * rank k is chosen with probability proportional to 1 / k^s.
+ */
+#[derive(Clone, Copy)]
+pub enum KeyDistribution {
+    Uniform,
+    Zipf(f64),
+}
+
+impl FromStr for KeyDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("uniform"), None) => Ok(KeyDistribution::Uniform),
+            (Some("zipf"), None) => Ok(KeyDistribution::Zipf(1.0)),
+            (Some("zipf"), Some(exponent)) => match exponent.parse::<f64>() {
+                Ok(e) if e > 0.0 => Ok(KeyDistribution::Zipf(e)),
+                _ => Err(format!("invalid zipf exponent: {}", exponent)),
+            },
+            _ => Err(format!("invalid key distribution: {}", s)),
+        }
+    }
+}
+
+/*
+ * Keys are grouped into classes by popularity rank so that latency can be
+ * broken down by how hot the key being updated is: the top 1% of keys are
+ * hot, the rest of the top 10% are warm and everything else is cold.
+ */
+#[derive(Clone, Copy)]
+pub enum KeyClass {
+    Hot,
+    Warm,
+    Cold,
+}
+
+pub static KEY_CLASSES: [KeyClass; 3] = [KeyClass::Hot, KeyClass::Warm, KeyClass::Cold];
+
+impl KeyClass {
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyClass::Hot => "Hot",
+            KeyClass::Warm => "Warm",
+            KeyClass::Cold => "Cold",
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/*
+ * The counter keys the writers spread over, named after the payload's name:
+ * a single key is the name itself, and otherwise key i is NAME_i.
+ */
+pub struct KeySpace {
+    names: Vec<String>,
+    cdf: Vec<f64>,
+}
+
+impl KeySpace {
+    pub fn new(name: &str, key_count: usize, distribution: KeyDistribution) -> KeySpace {
+        let names = if key_count == 1 {
+            vec![name.to_string()]
+        } else {
+            (0..key_count).map(|i| format!("{}_{}", name, i)).collect()
+        };
+        let weights: Vec<f64> = (1..=key_count)
+            .map(|rank| match distribution {
+                KeyDistribution::Uniform => 1.0,
+                KeyDistribution::Zipf(exponent) => 1.0 / (rank as f64).powf(exponent),
+            })
+            .collect();
+        let total: f64 = weights.iter().sum();
+
+        let mut cumulative = 0.0;
+        let cdf = weights
+            .iter()
+            .map(|w| {
+                cumulative += w / total;
+                cumulative
+            })
+            .collect();
+
+        KeySpace { names, cdf }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn name(&self, key: usize) -> &str {
+        &self.names[key]
+    }
+
+    // Pick the index of the next key to update
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        let u: f64 = rng.gen();
+        match self.cdf.binary_search_by(|p| p.partial_cmp(&u).unwrap()) {
+            Ok(i) | Err(i) => i.min(self.cdf.len() - 1),
+        }
+    }
+
+    pub fn class(&self, key: usize) -> KeyClass {
+        let hot = (self.len() / 100).max(1);
+        let warm = (self.len() / 10).max(1);
+
+        if key < hot {
+            KeyClass::Hot
+        } else if key < warm {
+            KeyClass::Warm
+        } else {
+            KeyClass::Cold
+        }
+    }
+
+    pub fn class_size(&self, class: KeyClass) -> usize {
+        (0..self.len()).filter(|&k| self.class(k).index() == class.index()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?} is not {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn parse_distributions() {
+        assert!(matches!("uniform".parse(), Ok(KeyDistribution::Uniform)));
+        assert!(matches!("zipf".parse(), Ok(KeyDistribution::Zipf(e)) if e == 1.0));
+        assert!(matches!("zipf:1.5".parse(), Ok(KeyDistribution::Zipf(e)) if e == 1.5));
+        for s in &["zipf:0", "zipf:-1", "zipf:x", "uniform:1", "normal"] {
+            assert!(s.parse::<KeyDistribution>().is_err(), "{} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(KeySpace::new("Steven", 1, KeyDistribution::Uniform).names(), &["Steven"]);
+        assert_eq!(
+            KeySpace::new("Alice", 3, KeyDistribution::Uniform).names(),
+            &["Alice_0", "Alice_1", "Alice_2"]
+        );
+    }
+
+    #[test]
+    fn cdf() {
+        assert_close(&KeySpace::new("k", 4, KeyDistribution::Uniform).cdf, &[0.25, 0.5, 0.75, 1.0]);
+        // Weights 1, 1/2 and 1/3 of a total of 11/6
+        assert_close(&KeySpace::new("k", 3, KeyDistribution::Zipf(1.0)).cdf, &[6.0 / 11.0, 9.0 / 11.0, 1.0]);
+        // Weights 1, 1/4 and 1/9 of a total of 49/36
+        assert_close(&KeySpace::new("k", 3, KeyDistribution::Zipf(2.0)).cdf, &[36.0 / 49.0, 45.0 / 49.0, 1.0]);
+    }
+
+    #[test]
+    fn sample_follows_the_distribution() {
+        let keys = KeySpace::new("k", 3, KeyDistribution::Zipf(1.0));
+        let mut rng = StdRng::from_seed([7; 32]);
+        let mut counts = [0u32; 3];
+        let samples = 100_000;
+        for _ in 0..samples {
+            counts[keys.sample(&mut rng)] += 1;
+        }
+
+        let expected = [6.0 / 11.0, 3.0 / 11.0, 2.0 / 11.0];
+        for (count, p) in counts.iter().zip(&expected) {
+            let frequency = *count as f64 / samples as f64;
+            assert!((frequency - p).abs() < 0.01, "{:?} is not close to {:?}", counts, expected);
+        }
+    }
+
+    #[test]
+    fn classes() {
+        let keys = KeySpace::new("k", 1000, KeyDistribution::Uniform);
+        assert_eq!(keys.class_size(KeyClass::Hot), 10);
+        assert_eq!(keys.class_size(KeyClass::Warm), 90);
+        assert_eq!(keys.class_size(KeyClass::Cold), 900);
+
+        // A single key is hot
+        let keys = KeySpace::new("k", 1, KeyDistribution::Uniform);
+        assert_eq!(keys.class_size(KeyClass::Hot), 1);
+    }
+}
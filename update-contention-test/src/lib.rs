@@ -0,0 +1,572 @@
+extern crate clap;
+#[macro_use]
+extern crate pg_test_common;
+extern crate postgres;
+extern crate rand;
+extern crate uuid;
+
+mod common;
+mod compaction;
+mod count;
+mod counter;
+mod keys;
+mod strategy;
+mod types;
+
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use pg_test_common::cli::{self, Options};
+use pg_test_common::durability::{self, Comparison, Durability};
+use pg_test_common::explain::Explainer;
+use pg_test_common::metrics;
+use pg_test_common::results::Results;
+use pg_test_common::scenario::{self, Cleanup, Values};
+use pg_test_common::sweep::Sweep;
+use pg_test_common::{bloat, statements, stats, work};
+use pg_test_common::{activity, locks, take_option};
+use postgres::Connection;
+
+use keys::KeySpace;
+use types::{Person, Workload};
+
+pub use keys::KeyDistribution;
+pub use strategy::Strategy;
+
+/*
+ * This program assumes the existence of two postgresql tables: person
+ * and person_count. The tables can be create with the following SQL statements:
+ *     CREATE TABLE person (
+ *       id serial PRIMARY KEY,
+ *       otherid uuid,
+ *       name text NOT NULL,
+ *       data text
+ *     );
+ *
+ *     CREATE INDEX person_data_idx ON person USING btree (data);
+ *     CREATE INDEX person_name_idx ON person USING btree (name);
+ *     CREATE UNIQUE INDEX person_otherid_idx ON person USING btree (otherid);
+ *
+ *     CREATE TABLE person_count (
+ *       name text NOT NULL,
+ *       bucket integer NOT NULL DEFAULT 0,
+ *       count bigint NOT NULL,
+ *       PRIMARY KEY (name, bucket)
+ *     );
+ *
+ *     CREATE TABLE person_count_delta (
+ *       id bigserial PRIMARY KEY,
+ *       name text NOT NULL,
+ *       delta bigint NOT NULL
+ *     );
+ *
+ *     CREATE INDEX person_count_delta_name_idx ON person_count_delta USING btree (name);
+ *
+ * Only the bucketed strategies spread the tally over buckets; the others
+ * always use bucket 0 so that every strategy can share the same table
+ * definition. The trigger strategy creates (and drops) its own trigger on
+ * person.
+ */
+
+const THREAD_COUNT: u32 = 16;
+const THREAD_WRITES: u32 = 1000;
+const BUCKET_COUNT: u32 = 100;
+const KEY_COUNT: usize = 1;
+
+fn usage() {
+    println!("Usage: update-contention-test PG_URL [THREAD_COUNT] [THREAD_WRITES] [BUCKET_COUNT] [--replica-url URL] [--durability LIST] [--scenario FILE] [--explain DIR] [--save FILE] [--compare FILE] [--threshold PCT] [--trials N] [--order fixed|alternate|random] [--table FILE] [--format text|json] [--precision DIGITS] [--max-latency MS] [--histogram-log DIR] [--log-interval MS] [--metrics-port PORT] [--metrics-textfile FILE] [--set NAME=VALUE]...");
+    println!();
+    println!("Every strategy is run with THREAD_COUNT threads (Default: 16) making THREAD_WRITES");
+    println!("writes each (Default: 1000), and the bucketed ones spread them over BUCKET_COUNT");
+    println!("buckets (Default: 100).");
+    println!();
+    println!("The strategies, compaction, counter keys and the count reader, wait sampler and");
+    println!("lock monitor are set by a --scenario FILE, or by the options of pg-test counter.");
+    println!();
+    println!("With --replica-url the reads go to the standby at URL while the writes go to the");
+    println!("primary, and a separate thread polls the standby for the transaction of each");
+    println!("committed write to report how often a committed counter value wasn't visible");
+    println!("there yet and how long it took to become visible.");
+    println!();
+    println!("--durability runs every phase once for each of a comma separated LIST of");
+    println!("synchronous_commit settings (on, off, local, remote_write) and unlogged, which");
+    println!("makes the phase's tables unlogged, and compares their throughput and latency");
+    println!("with those of the first one given.");
+    println!();
+    println!("--scenario runs the strategies, counts, keys, payload and cleanup described by");
+    println!("a TOML FILE. Arguments given on the command line override it; a positional");
+    println!("argument given as - keeps the scenario's setting.");
+    println!();
+    println!("With --explain the statements of each phase are run once with EXPLAIN ANALYZE");
+    println!("before it starts and the plans are written to DIR.");
+    println!();
+    println!("--save writes the throughput and latency percentiles of each phase to FILE,");
+    println!("and --compare reports their change against a FILE saved by an earlier run,");
+    println!("flagging changes for the worse beyond PCT percent (Default: 10).");
+    println!();
+    println!("--trials runs every phase N times (Default: 1) and reports the mean, standard");
+    println!("deviation and 95% confidence interval of each result across the trials.");
+    println!("--order sets the order of the phases on each trial: fixed, alternate (reversed");
+    println!("on every other trial) or random (Default: fixed). The latencies of every pair");
+    println!("of phases are compared with a Mann-Whitney U test at the end of the run.");
+    println!();
+    println!("THREAD_COUNT and BUCKET_COUNT may be lists of values and ranges such as 1..64*2");
+    println!("or 1,10..100+10 to run every combination of them, and --table writes the");
+    println!("throughput and latency percentiles of each combination to FILE as CSV.");
+    println!();
+    println!("Latencies are recorded to DIGITS significant figures (Default: 3) up to MS");
+    println!("milliseconds (Default: 60000); larger ones are counted as overflows. With");
+    println!("--histogram-log the latencies of every MS interval (Default: 1000) of each phase");
+    println!("are written to DIR as an HdrHistogram interval log.");
+    println!();
+    println!("--metrics-port serves live Prometheus metrics on localhost:PORT, and");
+    println!("--metrics-textfile keeps them in FILE for node_exporter's textfile collector.");
+    println!();
+    println!("--set sets NAME to VALUE on every connection the workers open, and may be");
+    println!("repeated. The settings are recorded with the results.");
+}
+
+// A positional count, or the list of counts to sweep over
+fn sweep_arg(args: &[String], index: usize, name: &str, scenario: Option<&Values>, default: u32) -> Vec<u32> {
+    cli::resolve_counts(scenario::positional(args, index), scenario, default).unwrap_or_else(|e| {
+        eprintln!("{}: {}", name, e);
+        usage();
+        process::exit(1)
+    })
+}
+
+fn phase_name(
+    strategy: Strategy,
+    compact_interval: Option<Duration>,
+    durability: Option<Durability>,
+) -> String {
+    let name = match compact_interval {
+        Some(_) => format!("{} (compacted)", strategy.name()),
+        None => strategy.name().to_string(),
+    };
+    match durability {
+        Some(durability) => durability.label(&name),
+        None => name,
+    }
+}
+
+// An optional interval in milliseconds, where 0 means disabled
+fn interval(millis: Option<u64>) -> Option<Duration> {
+    millis.filter(|&millis| millis > 0).map(Duration::from_millis)
+}
+
+/*
+ * Capture the plans of the statements the phase is about to run. Each one is
+ * rolled back, so the phase still starts from the state setup left behind.
+ */
+fn explain_phase(conn: &Connection, dir: &str, phase: &str, strategy: Strategy, workload: &Workload) {
+    let mut explainer = Explainer::new(dir, common::APP, phase);
+    let name = workload.keys.name(0);
+    let p = Person::new(name, &workload.data);
+
+    strategy.explain(&mut explainer, conn, &p, strategy.bucket(0, 0, workload.bucket_count));
+    if workload.count_interval.is_some() {
+        explainer.explain(conn, "count(*)", count::COUNT_STAR_SQL, &[&name], &["person_name_idx"]);
+    }
+
+    explainer.finish();
+}
+
+fn run_phase(
+    conn: &Connection,
+    strategy: Strategy,
+    compact_interval: Option<Duration>,
+    durability: Option<Durability>,
+    workload: Arc<Workload>,
+    results: &mut Results,
+    comparison: &mut Comparison,
+) {
+    let phase = phase_name(strategy, compact_interval, durability);
+
+    if let Some(durability) = durability {
+        durability.setup(conn, &common::TABLES);
+    }
+    strategy.setup(conn, workload.keys.names());
+    if let Some(ref dir) = workload.explain_dir {
+        explain_phase(conn, dir, &phase, strategy, &workload);
+    }
+    let bloat_before = bloat::Snapshot::take(conn, &common::TABLES);
+    let statements_tracked = statements::reset(conn, &[]);
+    let stats_before = stats::Snapshot::take(conn);
+    let rows_before = work::count_rows(conn, "person");
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let compactor = compact_interval.map(|interval| {
+        compaction::spawn(Arc::clone(&workload), durability, interval, Arc::clone(&stop))
+    });
+    let wait_sampler = workload.wait_sample_interval.map(|interval| {
+        activity::spawn(
+            workload.url.clone(),
+            common::APP.to_string(),
+            interval,
+            Arc::clone(&stop),
+        )
+    });
+    let lock_monitor = workload.lock_sample_interval.map(|interval| {
+        locks::spawn(
+            workload.url.clone(),
+            common::APP.to_string(),
+            interval,
+            Arc::clone(&stop),
+        )
+    });
+    let count_reader = workload.count_interval.map(|interval| {
+        count::spawn(strategy, Arc::clone(&workload), interval, Arc::clone(&stop))
+    });
+
+    metrics::start_phase(strategy.arg(), &results.label(&phase));
+    let start = Instant::now();
+    let (work, key_writes, (read_histogram, write_histogram), lag) =
+        counter::run_threads(strategy, durability, Arc::clone(&workload));
+    let end = Instant::now();
+
+    stop.store(true, Ordering::SeqCst);
+    if let Some(handle) = compactor {
+        match handle.join() {
+            Ok(compact_histogram) => {
+                report!("Compactions: {}", compact_histogram.entries());
+                common::print_percentiles("Compaction", &compact_histogram);
+            }
+            Err(_) => report!("compaction thread panicked"),
+        }
+    }
+    if let Some(handle) = count_reader {
+        match handle.join() {
+            Ok(count_results) => count::print_results(&count_results),
+            Err(_) => report!("count comparison thread panicked"),
+        }
+    }
+    if let Some(handle) = wait_sampler {
+        match handle.join() {
+            Ok(wait_events) => wait_events.print(&phase),
+            Err(_) => report!("wait event sampler thread panicked"),
+        }
+    }
+    if let Some(handle) = lock_monitor {
+        match handle.join() {
+            Ok(lock_summary) => lock_summary.print(&phase),
+            Err(_) => report!("lock monitor thread panicked"),
+        }
+    }
+    report!("{} updates: {:?}", phase, end.duration_since(start));
+    work.print(&phase);
+    let mut latencies = vec![("Read", &read_histogram), ("Write", &write_histogram)];
+    if workload.replica_url.is_some() {
+        lag.print(&phase);
+        latencies.push(("Replica lag", &lag.visible_after));
+    }
+    results.add_phase(&phase, key_writes.iter().sum(), end.duration_since(start), &latencies);
+    if let Some(durability) = durability {
+        let base_label = results.label(&phase_name(strategy, compact_interval, None));
+        comparison.add(
+            &base_label,
+            durability,
+            key_writes.iter().sum(),
+            end.duration_since(start),
+            &write_histogram,
+        );
+    }
+
+    stats::print_delta(&phase, &stats_before, &stats::Snapshot::take_settled(conn));
+    if statements_tracked {
+        statements::print_top(conn, &phase, &[]);
+    }
+    bloat::print_report(conn, &phase, &bloat_before, &bloat::Snapshot::take(conn, &common::TABLES));
+
+    work::verify_rows(&phase, "person", rows_before, work::count_rows(conn, "person"), &work);
+
+    // The tallies only match the phase's writes when it started from empty tables
+    if workload.cleanup == Cleanup::Phase {
+        common::verify_tally(conn, &phase, &workload.keys, &key_writes);
+    }
+
+    strategy.teardown(conn);
+    if workload.cleanup == Cleanup::Phase {
+        common::delete_tables(conn);
+    }
+    if let Some(durability) = durability {
+        durability.teardown(conn, &common::TABLES);
+    }
+}
+
+/*
+ * What to run: the strategies to compare, the counts to sweep over, the
+ * writes each thread makes, the counter keys they spread over, the optional
+ * background workers, the standby to read from, the durabilities to run
+ * every phase with and the options shared with the other utilities.
+ * Intervals of None disable their worker, and no durabilities leave the
+ * server's setting alone.
+ */
+pub struct Config {
+    pub url: String,
+    pub replica_url: Option<String>,
+    pub threads: Vec<u32>,
+    pub writes: u32,
+    pub buckets: Vec<u32>,
+    pub strategies: Vec<Strategy>,
+    pub compact_interval: Option<Duration>,
+    pub key_count: usize,
+    pub key_distribution: KeyDistribution,
+    pub count_interval: Option<Duration>,
+    pub wait_sample_interval: Option<Duration>,
+    pub lock_sample_interval: Option<Duration>,
+    pub durability: Vec<Durability>,
+    pub options: Options,
+}
+
+impl Config {
+    // Parse update-contention-test's positional command line
+    pub fn from_args(mut args: Vec<String>) -> Config {
+        let options = Options::from_args(&mut args, common::APP);
+        let replica_url = take_option(&mut args, "--replica-url");
+        let scenario = &options.scenario;
+        let durability = durability::from_args(&mut args, scenario);
+        let invalid = |name: &str, e: String| -> ! {
+            eprintln!("{}: {}", name, e);
+            usage();
+            process::exit(1)
+        };
+
+        let url = match scenario::positional(&args, 1) {
+            Some(url) => url.to_string(),
+            None => scenario.connection.url.clone().unwrap_or_else(|| {
+                usage();
+                process::exit(1)
+            }),
+        };
+        let threads = sweep_arg(&args, 2, "THREAD_COUNT", scenario.threads.as_ref(), THREAD_COUNT);
+        let writes = match scenario::positional(&args, 3) {
+            Some(arg) => cli::parse_count(arg).unwrap_or_else(|e| invalid("THREAD_WRITES", e)),
+            None => scenario.iterations.unwrap_or(THREAD_WRITES),
+        };
+        let buckets = sweep_arg(&args, 4, "BUCKET_COUNT", scenario.buckets.as_ref(), BUCKET_COUNT);
+        // Everything else is only set by the scenario, or by pg-test counter
+        if let Some(arg) = args.get(5) {
+            eprintln!("unexpected argument {}: the other settings are given by --scenario", arg);
+            usage();
+            process::exit(1);
+        }
+
+        Config {
+            url,
+            replica_url,
+            threads,
+            writes,
+            buckets,
+            strategies: scenario.select_phases(&strategy::ALL, |strategy| strategy.arg()),
+            compact_interval: interval(scenario.compact_interval),
+            key_count: scenario.keys.unwrap_or(KEY_COUNT),
+            key_distribution: scenario.key_distribution().unwrap_or(KeyDistribution::Uniform),
+            count_interval: interval(scenario.count_interval),
+            wait_sample_interval: interval(scenario.wait_sample_interval),
+            lock_sample_interval: interval(scenario.lock_sample_interval),
+            durability,
+            options,
+        }
+    }
+
+    // The matches of subcommand()
+    pub fn from_matches(matches: &ArgMatches) -> Config {
+        let options = Options::from_matches(matches, common::APP);
+        let scenario = &options.scenario;
+        let interval = |name, scenario: Option<u64>| interval(cli::parsed(matches, name).or(scenario));
+
+        Config {
+            url: cli::url(matches, scenario),
+            replica_url: cli::string(matches, "replicaUrl"),
+            threads: cli::sweep_counts(matches, "threads", scenario.threads.as_ref(), THREAD_COUNT),
+            writes: cli::parsed(matches, "iterations")
+                .or(scenario.iterations)
+                .unwrap_or(THREAD_WRITES),
+            buckets: cli::sweep_counts(matches, "buckets", scenario.buckets.as_ref(), BUCKET_COUNT),
+            strategies: match matches.value_of("strategies") {
+                Some(strategies) => parse_strategies(strategies).unwrap(),
+                None => scenario.select_phases(&strategy::ALL, |strategy| strategy.arg()),
+            },
+            compact_interval: interval("compactInterval", scenario.compact_interval),
+            key_count: cli::parsed(matches, "keys").or(scenario.keys).unwrap_or(KEY_COUNT),
+            key_distribution: cli::parsed(matches, "keyDistribution")
+                .or_else(|| scenario.key_distribution())
+                .unwrap_or(KeyDistribution::Uniform),
+            count_interval: interval("countInterval", scenario.count_interval),
+            wait_sample_interval: interval("waitSampleInterval", scenario.wait_sample_interval),
+            lock_sample_interval: interval("lockSampleInterval", scenario.lock_sample_interval),
+            durability: durability::from_matches(matches, scenario),
+            options,
+        }
+    }
+}
+
+// A comma separated list of strategies
+fn parse_strategies(strategies: &str) -> Result<Vec<Strategy>, String> {
+    strategies.split(',').map(str::parse).collect()
+}
+
+pub fn subcommand<'a, 'b>(name: &str) -> App<'a, 'b> {
+    let interval = |name, long, help| {
+        Arg::with_name(name)
+            .help(help)
+            .long(long)
+            .value_name("MS")
+            .takes_value(true)
+            .validator(cli::parses::<u64>)
+    };
+
+    SubCommand::with_name(name)
+        .about("Compare strategies for keeping a frequently updated tally counter")
+        .args(&cli::args("Writes per thread (Default: 1000)"))
+        .arg(Arg::with_name("buckets")
+             .help("Buckets of the bucketed strategies, or a list of counts to sweep (Default: 100)")
+             .long("buckets")
+             .value_name("COUNT")
+             .takes_value(true)
+             .validator(cli::counts))
+        .arg(Arg::with_name("strategies")
+             .help("Comma separated strategies to run: single-cell, bucketed, thread-bucketed, \
+                    select-for-update, advisory-lock, ledger, trigger (Default: all of them)")
+             .long("strategies")
+             .value_name("LIST")
+             .takes_value(true)
+             .validator(|strategies| parse_strategies(&strategies).map(|_| ())))
+        .arg(Arg::with_name("keys")
+             .help("Counter keys to spread the writes over (Default: 1)")
+             .long("keys")
+             .value_name("COUNT")
+             .takes_value(true)
+             .validator(cli::count))
+        .arg(Arg::with_name("keyDistribution")
+             .help("How keys are chosen: uniform, zipf or zipf:EXPONENT (Default: uniform)")
+             .long("key-distribution")
+             .value_name("DISTRIBUTION")
+             .takes_value(true)
+             .validator(cli::parses::<KeyDistribution>))
+        .arg(Arg::with_name("replicaUrl")
+             .help("Send reads to this standby and measure how far behind the primary it is")
+             .long("replica-url")
+             .value_name("URL")
+             .takes_value(true))
+        .arg(durability::arg())
+        .arg(interval("compactInterval", "compact-interval",
+                      "Also run each bucketed strategy with its buckets compacted at this interval"))
+        .arg(interval("countInterval", "count-interval",
+                      "Compare count(*), reltuples and person_count at this interval while writing"))
+        .arg(interval("waitSampleInterval", "wait-sample-interval",
+                      "Sample what the writers wait on from pg_stat_activity at this interval"))
+        .arg(interval("lockSampleInterval", "lock-sample-interval",
+                      "Capture blocked backends and lock chains from pg_locks at this interval"))
+}
+
+pub fn run(config: Config) {
+    let options = &config.options;
+    let scenario = &options.scenario;
+    let compact_interval = config.compact_interval;
+    let mut sweep = Sweep::new();
+    sweep.add("threads", config.threads.clone());
+    sweep.add("buckets", config.buckets.clone());
+
+    // A standby can't read unlogged tables
+    if config.replica_url.is_some() && config.durability.contains(&Durability::Unlogged) {
+        eprintln!("--durability unlogged can't be used with --replica-url");
+        process::exit(1);
+    }
+
+    // Every phase runs once per durability, or as the server is set up without any
+    let durabilities: Vec<Option<Durability>> = if config.durability.is_empty() {
+        vec![None]
+    } else {
+        config.durability.iter().cloned().map(Some).collect()
+    };
+    let mut phases = Vec::new();
+    for &strategy in &config.strategies {
+        for &durability in &durabilities {
+            phases.push((strategy, None, durability));
+            if strategy.is_bucketed() && compact_interval.is_some() {
+                phases.push((strategy, compact_interval, durability));
+            }
+        }
+    }
+
+    let conn = common::connect(&config.url);
+    options.settings.check(&conn);
+
+    // Start from empty tables so an aborted run can't skew the tally checks
+    if scenario.cleanup == Cleanup::Phase {
+        common::delete_tables(&conn);
+    }
+
+    let mut results = Results::new(common::APP, &options.settings);
+    let mut comparison = Comparison::new(&config.durability);
+    let points = sweep.points();
+    for (p, point) in points.iter().enumerate() {
+        if sweep.is_sweep() {
+            report!("Sweep point {} of {}: {}", p + 1, points.len(), point.describe());
+            results.set_point(point);
+        }
+        let workload = Arc::new(Workload {
+            url: config.url.clone(),
+            replica_url: config.replica_url.clone(),
+            thread_count: point.get("threads") as i32,
+            thread_writes: config.writes as i32,
+            bucket_count: point.get("buckets") as i32,
+            keys: KeySpace::new(
+                &scenario.payload.name,
+                config.key_count.max(1),
+                config.key_distribution,
+            ),
+            count_interval: config.count_interval,
+            wait_sample_interval: config.wait_sample_interval,
+            lock_sample_interval: config.lock_sample_interval,
+            explain_dir: options.explain_dir.clone(),
+            data: scenario.payload.data(),
+            cleanup: scenario.cleanup,
+            settings: options.settings.clone(),
+        });
+
+        for trial in 0..options.trials {
+            let trial_phases = options.order.arrange(&phases, trial);
+            if options.trials > 1 {
+                let names: Vec<String> = trial_phases
+                    .iter()
+                    .map(|&(strategy, interval, durability)| {
+                        phase_name(strategy, interval, durability)
+                    })
+                    .collect();
+                report!("Trial {} of {}: {}", trial + 1, options.trials, names.join(", "));
+            }
+
+            for (i, &(strategy, phase_compact_interval, durability)) in
+                trial_phases.iter().enumerate()
+            {
+                if p > 0 || trial > 0 || i > 0 {
+                    thread::sleep(Duration::from_secs(1));
+                }
+
+                run_phase(
+                    &conn,
+                    strategy,
+                    phase_compact_interval,
+                    durability,
+                    Arc::clone(&workload),
+                    &mut results,
+                    &mut comparison,
+                );
+            }
+        }
+    }
+
+    if scenario.cleanup == Cleanup::End {
+        common::delete_tables(&conn);
+    }
+    comparison.print();
+    options.output.finish(&results);
+    metrics::finish();
+}
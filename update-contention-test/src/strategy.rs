@@ -0,0 +1,266 @@
+use std::str::FromStr;
+
+use pg_test_common::explain::Explainer;
+use postgres::transaction::Transaction;
+use postgres::{self, Connection};
+
+use types::Person;
+
+static INSERT_PERSON_SQL: &str = "INSERT INTO person (otherid, name, data) VALUES ($1, $2, $3)";
+static UPSERT_COUNT_SQL: &str = "INSERT INTO person_count (name, count, bucket) VALUES ($1, 1, $2) \
+                                 ON CONFLICT (name, bucket) DO UPDATE SET count = person_count.count + 1";
+static SELECT_FOR_UPDATE_SQL: &str =
+    "SELECT count FROM person_count WHERE name = $1 AND bucket = 0 FOR UPDATE";
+static ADVISORY_LOCK_SQL: &str = "SELECT pg_advisory_xact_lock(hashtext($1))";
+static SELECT_COUNT_SQL: &str = "SELECT count FROM person_count WHERE name = $1 AND bucket = 0";
+static UPDATE_COUNT_SQL: &str = "UPDATE person_count SET count = $2 WHERE name = $1 AND bucket = 0";
+static INSERT_DELTA_SQL: &str = "INSERT INTO person_count_delta (name, delta) VALUES ($1, 1)";
+
+/*
+ * The different ways of maintaining the person_count tally that can be
+ * compared. Every strategy inserts a row into person for each write and
+ * bumps the tally for that person's name in the same transaction.
+ */
+#[derive(Clone, Copy)]
+pub enum Strategy {
+    SingleCell,
+    Bucketed,
+    ThreadBucketed,
+    SelectForUpdate,
+    AdvisoryLock,
+    Ledger,
+    Trigger,
+}
+
+pub static ALL: [Strategy; 7] = [
+    Strategy::SingleCell,
+    Strategy::Bucketed,
+    Strategy::ThreadBucketed,
+    Strategy::SelectForUpdate,
+    Strategy::AdvisoryLock,
+    Strategy::Ledger,
+    Strategy::Trigger,
+];
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "single-cell" => Ok(Strategy::SingleCell),
+            "bucketed" => Ok(Strategy::Bucketed),
+            "thread-bucketed" => Ok(Strategy::ThreadBucketed),
+            "select-for-update" => Ok(Strategy::SelectForUpdate),
+            "advisory-lock" => Ok(Strategy::AdvisoryLock),
+            "ledger" => Ok(Strategy::Ledger),
+            "trigger" => Ok(Strategy::Trigger),
+            _ => Err(format!("invalid strategy: {}", s)),
+        }
+    }
+}
+
+impl Strategy {
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::SingleCell => "Single cell",
+            Strategy::Bucketed => "Bucketed",
+            Strategy::ThreadBucketed => "Thread bucketed",
+            Strategy::SelectForUpdate => "Select for update",
+            Strategy::AdvisoryLock => "Advisory lock",
+            Strategy::Ledger => "Ledger",
+            Strategy::Trigger => "Trigger",
+        }
+    }
+
+    // The name the strategy is given on the command line
+    pub fn arg(self) -> &'static str {
+        match self {
+            Strategy::SingleCell => "single-cell",
+            Strategy::Bucketed => "bucketed",
+            Strategy::ThreadBucketed => "thread-bucketed",
+            Strategy::SelectForUpdate => "select-for-update",
+            Strategy::AdvisoryLock => "advisory-lock",
+            Strategy::Ledger => "ledger",
+            Strategy::Trigger => "trigger",
+        }
+    }
+
+    pub fn is_bucketed(self) -> bool {
+        matches!(self, Strategy::Bucketed | Strategy::ThreadBucketed)
+    }
+
+    /*
+     * Prepare the database for a phase. The read-modify-write strategies
+     * expect the counter row to already exist so that concurrent writers
+     * never race on creating it, and the trigger strategy installs the
+     * trigger for the duration of its phase only. Any trigger left behind by
+     * an aborted run is dropped so it can't double count other strategies.
+     */
+    pub fn setup(self, conn: &Connection, names: &[String]) {
+        drop_trigger(conn);
+
+        match self {
+            Strategy::SelectForUpdate | Strategy::AdvisoryLock => {
+                conn.execute(
+                    "INSERT INTO person_count (name, bucket, count) \
+                     SELECT unnest($1::text[]), 0, 0 \
+                     ON CONFLICT (name, bucket) DO NOTHING",
+                    &[&names],
+                )
+                .unwrap();
+            }
+            Strategy::Trigger => {
+                conn.batch_execute(
+                    "CREATE OR REPLACE FUNCTION person_count_trigger() RETURNS trigger AS $$ \
+                     BEGIN \
+                         INSERT INTO person_count (name, bucket, count) VALUES (NEW.name, 0, 1) \
+                         ON CONFLICT (name, bucket) DO UPDATE SET count = person_count.count + 1; \
+                         RETURN NEW; \
+                     END; \
+                     $$ LANGUAGE plpgsql; \
+                     CREATE TRIGGER person_count_trigger AFTER INSERT ON person \
+                     FOR EACH ROW EXECUTE PROCEDURE person_count_trigger();",
+                )
+                .unwrap();
+            }
+            _ => (),
+        }
+    }
+
+    pub fn teardown(self, conn: &Connection) {
+        if let Strategy::Trigger = self {
+            drop_trigger(conn);
+        }
+    }
+
+    /*
+     * Pick the bucket a writer should increment. Only the bucketed
+     * strategies spread the tally; everything else uses bucket 0.
+     */
+    pub fn bucket(self, thread_id: i32, random_bucket: i32, bucket_count: i32) -> i32 {
+        match self {
+            Strategy::Bucketed => random_bucket,
+            Strategy::ThreadBucketed => thread_id % bucket_count,
+            _ => 0,
+        }
+    }
+
+    pub fn read_sql(self) -> &'static str {
+        match self {
+            Strategy::Bucketed | Strategy::ThreadBucketed => {
+                "SELECT sum(count) FROM person_count WHERE name = $1"
+            }
+            Strategy::Ledger => {
+                "SELECT (SELECT coalesce(sum(count), 0) FROM person_count WHERE name = $1) \
+                 + (SELECT coalesce(sum(delta), 0) FROM person_count_delta WHERE name = $1)"
+            }
+            _ => SELECT_COUNT_SQL,
+        }
+    }
+
+    pub fn write(self, trans: &Transaction, p: &Person, bucket: i32) -> postgres::Result<()> {
+        trans
+            .execute(INSERT_PERSON_SQL, &[&p.otherid, &p.name, &p.data])?;
+
+        match self {
+            Strategy::SingleCell | Strategy::Bucketed | Strategy::ThreadBucketed => {
+                trans.execute(UPSERT_COUNT_SQL, &[&p.name, &bucket])?;
+            }
+            Strategy::SelectForUpdate => {
+                let rows = trans.query(SELECT_FOR_UPDATE_SQL, &[&p.name])?;
+                let count: i64 = rows.get(0).get(0);
+                trans
+                    .execute(UPDATE_COUNT_SQL, &[&p.name, &(count + 1)])?;
+            }
+            Strategy::AdvisoryLock => {
+                trans.execute(ADVISORY_LOCK_SQL, &[&p.name])?;
+                let rows = trans.query(SELECT_COUNT_SQL, &[&p.name])?;
+                let count: i64 = rows.get(0).get(0);
+                trans
+                    .execute(UPDATE_COUNT_SQL, &[&p.name, &(count + 1)])?;
+            }
+            Strategy::Ledger => {
+                trans.execute(INSERT_DELTA_SQL, &[&p.name])?;
+            }
+            // The trigger on person does the increment for us
+            Strategy::Trigger => (),
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Explain the statements a writer runs for this strategy, plus the
+     * tally read, for the person p. Lookups of a person_count row are
+     * expected to go through its primary key.
+     */
+    pub fn explain(self, explainer: &mut Explainer, conn: &Connection, p: &Person, bucket: i32) {
+        let one: i64 = 1;
+
+        explainer.explain(
+            conn,
+            "person insert",
+            INSERT_PERSON_SQL,
+            &[&p.otherid, &p.name, &p.data],
+            &[],
+        );
+
+        match self {
+            Strategy::SingleCell | Strategy::Bucketed | Strategy::ThreadBucketed => {
+                explainer.explain(
+                    conn,
+                    "counter upsert",
+                    UPSERT_COUNT_SQL,
+                    &[&p.name, &bucket],
+                    &["person_count_pkey"],
+                );
+            }
+            Strategy::SelectForUpdate | Strategy::AdvisoryLock => {
+                let (lock_name, lock_sql, lock_indexes) = match self {
+                    Strategy::SelectForUpdate => {
+                        ("counter select for update", SELECT_FOR_UPDATE_SQL, &["person_count_pkey"][..])
+                    }
+                    _ => ("advisory lock", ADVISORY_LOCK_SQL, &[][..]),
+                };
+                explainer.explain(conn, lock_name, lock_sql, &[&p.name], lock_indexes);
+                explainer.explain(
+                    conn,
+                    "counter update",
+                    UPDATE_COUNT_SQL,
+                    &[&p.name, &one],
+                    &["person_count_pkey"],
+                );
+            }
+            Strategy::Ledger => {
+                explainer.explain(conn, "delta insert", INSERT_DELTA_SQL, &[&p.name], &[]);
+            }
+            Strategy::Trigger => (),
+        }
+
+        let mut read_indexes = vec!["person_count_pkey"];
+        if let Strategy::Ledger = self {
+            read_indexes.push("person_count_delta_name_idx");
+        }
+        explainer.explain(conn, "counter read", self.read_sql(), &[&p.name], &read_indexes);
+    }
+}
+
+fn drop_trigger(conn: &Connection) {
+    conn.batch_execute("DROP TRIGGER IF EXISTS person_count_trigger ON person;")
+        .unwrap();
+}
+
+/*
+ * Fold the outstanding ledger deltas for a name into its person_count row.
+ * Deleting the deltas and adding them to the tally happen in one statement
+ * so a concurrent reader never sees them counted twice or not at all.
+ */
+pub fn rollup_ledger(conn: &Connection, name: &str) -> postgres::Result<u64> {
+    conn.execute(
+        "WITH moved AS (DELETE FROM person_count_delta WHERE name = $1 RETURNING delta) \
+         INSERT INTO person_count (name, bucket, count) \
+         SELECT $1, 0, coalesce(sum(delta), 0) FROM moved \
+         ON CONFLICT (name, bucket) DO UPDATE SET count = person_count.count + excluded.count",
+        &[&name],
+    )
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use pg_test_common::histogram::Histogram;
+use pg_test_common::scenario::Cleanup;
+use pg_test_common::settings::Settings;
+use pg_test_common::work::Work;
+use uuid::Uuid;
+
+use keys::KeySpace;
+
+pub type HistogramPair = (Histogram, Histogram);
+
+pub struct Person {
+    pub _id: i32,
+    pub otherid: Uuid,
+    pub name: String,
+    pub data: Option<String>,
+}
+
+/*
+ * The parameters of a run, shared by every phase and writer thread.
+ */
+pub struct Workload {
+    pub url: String,
+    // Reads go to this standby instead of the primary
+    pub replica_url: Option<String>,
+    pub thread_count: i32,
+    pub thread_writes: i32,
+    pub bucket_count: i32,
+    pub keys: KeySpace,
+    pub count_interval: Option<Duration>,
+    pub wait_sample_interval: Option<Duration>,
+    pub lock_sample_interval: Option<Duration>,
+    pub explain_dir: Option<String>,
+    // The data column of every person inserted
+    pub data: String,
+    pub cleanup: Cleanup,
+    // Set on every connection the writers, compactor and count reader open
+    pub settings: Settings,
+}
+
+/*
+ * What a single writer thread did during a phase: its transactions, read and
+ * write latencies for each key class (indexed by KeyClass::index) and the
+ * number of committed increments for each key.
+ */
+pub struct ThreadResults {
+    pub work: Work,
+    pub histograms: Vec<HistogramPair>,
+    pub key_writes: Vec<u64>,
+}
+
+impl Person {
+    pub fn new(name: &str, data: &str) -> Person {
+        Person {
+            _id: 0,
+            otherid: Uuid::new_v4(),
+            name: name.to_string(),
+            data: Some(data.to_string()),
+        }
+    }
+}